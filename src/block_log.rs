@@ -0,0 +1,142 @@
+use crate::{dac::psl, initable_static, maybe::UnifiedError};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    time::SystemTime,
+};
+use url::Url;
+
+initable_static! {
+    BLOCK_LOG_FILE = |path: &Path| -> Result<Mutex<LineWriter<File>>, UnifiedError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Can't open block log '{}': {e}", path.display()))?;
+        Ok(Mutex::new(LineWriter::new(file)))
+    };
+}
+
+const RECENT_LIMIT: usize = 20;
+
+// последние блокировки для страницы статуса
+static RECENT: Mutex<VecDeque<BlockEvent>> = Mutex::new(VecDeque::new());
+
+/// Resource blocked by DAC rule, one JSON line of the block log
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BlockEvent {
+    /// Unix time in seconds
+    pub time: u64,
+    pub url: String,
+    /// Page which requested the resource
+    pub page: String,
+    pub rule: String,
+    pub pattern_type: String,
+    pub resource_type: String,
+    /// Size of the removed content, 0 if it's unknown (the resource was not downloaded)
+    pub bytes_saved: u64,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+pub fn record(event: BlockEvent) {
+    tracing::debug!(
+        url = %event.url,
+        page = %event.page,
+        rule = %event.rule,
+        pattern_type = %event.pattern_type,
+        resource_type = %event.resource_type,
+        bytes_saved = event.bytes_saved,
+        "blocked"
+    );
+    {
+        let mut recent = RECENT.lock();
+        if recent.len() >= RECENT_LIMIT {
+            recent.pop_front();
+        }
+        recent.push_back(event.clone());
+    }
+    let Some(file) = BLOCK_LOG_FILE.get() else {
+        return;
+    };
+    let result = serde_json::to_string(&event)
+        .map_err(UnifiedError::from)
+        .and_then(|line| Ok(writeln!(file.lock(), "{line}")?));
+    if let Err(e) = result {
+        tracing::warn!("Can't write block log: {e}");
+    }
+}
+
+/// Last blocked resources, newest first
+pub fn recent() -> Vec<BlockEvent> {
+    RECENT.lock().iter().rev().cloned().collect()
+}
+
+fn host_of(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_ascii_lowercase))
+        .unwrap_or_default()
+}
+
+fn print_top(title: &str, counts: HashMap<String, u64>, top: usize) {
+    let mut sorted: Vec<_> = counts.into_iter().collect();
+    sorted.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    println!("\n{title}:");
+    for (name, count) in sorted.into_iter().take(top) {
+        println!("{count:>12}  {name}");
+    }
+}
+
+/// `zhlob stats`: summary of the block log
+pub fn stats(log: &Path, top: usize) -> Result<(), UnifiedError> {
+    let reader: Box<dyn BufRead> = if log == Path::new("-") {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(log).map_err(|e| {
+            format!("Can't open block log '{}': {e}", log.display())
+        })?))
+    };
+
+    let mut total = 0u64;
+    let mut invalid = 0u64;
+    let mut domains: HashMap<String, u64> = HashMap::new();
+    let mut rules: HashMap<String, u64> = HashMap::new();
+    let mut saved: HashMap<String, u64> = HashMap::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim_ascii().is_empty() {
+            continue;
+        }
+        let Ok(event) = serde_json::from_str::<BlockEvent>(&line) else {
+            invalid += 1;
+            continue;
+        };
+        total += 1;
+
+        let host = host_of(&event.url);
+        let domain = psl::sld(&host).map_or_else(|| host.clone(), |d| d.to_string());
+        *domains.entry(domain).or_default() += 1;
+        *rules.entry(event.rule).or_default() += 1;
+        *saved.entry(host_of(&event.page)).or_default() += event.bytes_saved;
+    }
+
+    println!("Blocked: {total}");
+    if invalid > 0 {
+        println!("Invalid lines skipped: {invalid}");
+    }
+    print_top("Top blocked domains", domains, top);
+    print_top("Top rules", rules, top);
+    saved.retain(|_, bytes| *bytes > 0);
+    print_top("Bytes saved per host", saved, top);
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use crate::{
+    cli::{APP_NAME, CLI_MATCHES, Cli, ConfigCommands},
+    initable_static,
+    maybe::UnifiedError,
+    policy::HostRule,
+    proxy::cert::BASE_DIRS,
+};
+use clap::{ArgMatches, Command, CommandFactory, FromArgMatches};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+const CONFIG_ENV: &str = const_str::convert_ascii_case!(
+    shouty_snake,
+    const_str::concat!(APP_NAME, "_config")
+);
+
+/// Settings from the TOML config file.
+/// Flag values (`html-clean = false`, `listen = ":8080"`, ...) are collected in `flags` and used as defaults for the CLI,
+/// so the precedence is CLI > env > file > built-in default.
+#[derive(Deserialize, Serialize, Default, Clone)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct FileConfig {
+    /// Extra query params stripped from links (`name` for exact match, `prefix*` for prefix match)
+    pub blocked_params: Vec<String>,
+
+    /// Per-host overrides of the transformation settings
+    pub rules: Vec<HostRule>,
+
+    /// Proxy users: name = password
+    pub users: BTreeMap<String, String>,
+
+    #[serde(flatten)]
+    pub flags: toml::Table,
+
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+
+    /// xxh3 of the file content, used to rotate ETag marker on changes
+    #[serde(skip)]
+    pub hash: u64,
+}
+
+initable_static! {
+    CONFIG = reloadable |path: Option<PathBuf>| -> Result<FileConfig, UnifiedError> {
+        let Some(path) = path else {
+            return Ok(FileConfig::default());
+        };
+        let text = std::fs::read_to_string(&path)?;
+        let mut config: FileConfig = toml::from_str(&text)
+            .map_err(|e| format!("Invalid config file '{}': {e}", path.display()))?;
+
+        for (key, value) in &config.flags {
+            if !Cli::FLAGS.contains(&flag_id(key).as_str()) || key == "config" {
+                return Err(format!("Unknown option '{key}' in config file '{}'", path.display()).into());
+            }
+            if toml_to_arg_value(value).is_none() {
+                return Err(format!("Option '{key}' in config file '{}' must be a string, number or bool", path.display()).into());
+            }
+        }
+        config.path = Some(path);
+        config.hash = xxhash_rust::xxh3::xxh3_64(text.as_bytes());
+        Ok(config)
+    };
+}
+
+fn flag_id(key: &str) -> String {
+    key.replace('-', "_")
+}
+
+fn flag_key(id: &str) -> String {
+    id.replace('_', "-")
+}
+
+/// Config file path from `--config PATH`, `--config=PATH`, env or the default `~/.zhlob/config.toml` (if exists).
+/// Have to be resolved before the CLI parsing, since the file values are used as CLI defaults.
+pub fn config_path() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(p) = arg.to_str().and_then(|s| s.strip_prefix("--config=")) {
+            return Some(PathBuf::from(p));
+        }
+    }
+
+    if let Some(p) = std::env::var_os(CONFIG_ENV).filter(|p| !p.is_empty()) {
+        return Some(PathBuf::from(p));
+    }
+
+    let default_path = BASE_DIRS
+        .home_dir()
+        .join(const_str::concat!(".", APP_NAME))
+        .join("config.toml");
+    default_path.is_file().then_some(default_path)
+}
+
+fn toml_to_arg_value(value: &toml::Value) -> Option<String> {
+    Some(match value {
+        toml::Value::String(s) => s.clone(),
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) => f.to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        _ => return None,
+    })
+}
+
+fn arg_value_to_toml(raw: &str) -> toml::Value {
+    if let Ok(b) = raw.parse::<bool>() {
+        toml::Value::Boolean(b)
+    } else if let Ok(i) = raw.parse::<i64>() {
+        toml::Value::Integer(i)
+    } else if let Ok(f) = raw.parse::<f64>() {
+        toml::Value::Float(f)
+    } else {
+        toml::Value::String(raw.to_string())
+    }
+}
+
+/// Apply values from the config file as defaults of the CLI args
+pub fn with_file_defaults(mut cmd: Command, config: &FileConfig) -> Command {
+    for (key, value) in &config.flags {
+        if let Some(value) = toml_to_arg_value(value) {
+            cmd = cmd.mut_arg(flag_id(key), |a| a.default_value(value));
+        }
+    }
+    cmd
+}
+
+/// Parse the process args again with (possibly changed) config file values as defaults
+pub fn parse_cli(config: &FileConfig) -> Result<(Cli, ArgMatches), UnifiedError> {
+    let matches =
+        with_file_defaults(Cli::command(), config).try_get_matches_from(std::env::args_os())?;
+    Ok((Cli::from_arg_matches(&matches)?, matches))
+}
+
+/// Config keys of `Cli::RESTART_FLAGS` whose values in `matches` differ from the ones the proxy started with
+pub fn changed_restart_flags(matches: &ArgMatches) -> Vec<String> {
+    let raw = |m: &ArgMatches, id: &str| {
+        m.get_raw(id)
+            .map(|values| values.map(|v| v.to_os_string()).collect::<Vec<_>>())
+    };
+    Cli::RESTART_FLAGS
+        .iter()
+        .filter(|id| raw(&CLI_MATCHES, id) != raw(matches, id))
+        .map(|id| flag_key(id))
+        .collect()
+}
+
+fn effective_config(matches: &ArgMatches) -> FileConfig {
+    let mut flags = toml::Table::new();
+    for id in Cli::FLAGS.iter().filter(|id| **id != "config") {
+        if let Some(raw) = matches.get_raw(id).and_then(|mut r| r.next()) {
+            flags.insert(flag_key(id), arg_value_to_toml(&raw.to_string_lossy()));
+        }
+    }
+    FileConfig {
+        flags,
+        ..(*CONFIG::get()).clone()
+    }
+}
+
+fn dump(path: Option<&Path>) -> Result<(), UnifiedError> {
+    let text = toml::to_string_pretty(&effective_config(&CLI_MATCHES))?;
+    if let Some(p) = path.filter(|p| *p != Path::new("-")) {
+        std::fs::write(p, text)?;
+    } else {
+        print!("{text}");
+    }
+    Ok(())
+}
+
+pub fn run(args: &ConfigCommands) -> Result<(), UnifiedError> {
+    match args {
+        ConfigCommands::Dump { output } => {
+            if let Some(path) = &CONFIG::get().path {
+                tracing::info!("Config file: {}", path.display());
+            }
+            dump(output.as_deref())
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use crate::{
+    dac::{domains::DomainList, psl},
+    maybe::UnifiedError,
+};
+use lol_html::Selector;
+use std::{
+    borrow::Cow,
+    collections::{BTreeSet, HashMap, HashSet},
+    str::FromStr,
+};
+
+// Формат секции: строки `domain\tselector`, пустой domain - общее правило, `~domain` - исключение для домена
+
+/// Element hiding rules (`##`, `#?#`, `#@#`) collected by dacgen
+#[derive(Default)]
+pub struct CosmeticRulesBuilder {
+    hide: BTreeSet<(String, String)>,
+    exceptions: BTreeSet<(String, String)>,
+    global_exceptions: HashSet<String>,
+}
+
+impl CosmeticRulesBuilder {
+    /// Returns `false` if the line is not a cosmetic rule at all.
+    /// Unsupported cosmetic rules (scriptlets, css injections, html filters, invalid selectors) are consumed silently.
+    pub fn add_rule(&mut self, line: &str) -> bool {
+        let Some((domains, separator, selector)) =
+            ["##", "#@#", "#?#", "#@?#", "#$#", "#@$#", "#%#", "#@%#"]
+                .iter()
+                .filter_map(|sep| line.find(sep).map(|pos| (pos, *sep)))
+                .min()
+                .map(|(pos, sep)| (&line[..pos], sep, line[pos + sep.len()..].trim_ascii()))
+        else {
+            return false;
+        };
+
+        let is_exception = match separator {
+            "##" | "#?#" => false,
+            "#@#" | "#@?#" => true,
+            _ => return true,
+        };
+        // +js(...) - scriptlets uBO, ^ - html filters
+        if selector.is_empty()
+            || selector.starts_with("+js(")
+            || selector.starts_with('^')
+            || selector.contains(['\t', '\n'])
+            || Selector::from_str(selector).is_err()
+        {
+            return true;
+        }
+
+        // example.* и /regex/ домены не поддерживаются
+        let Some(DomainList {
+            include: positive,
+            exclude: negative,
+        }) = DomainList::parse(domains, ',')
+        else {
+            return true;
+        };
+
+        let selector = selector.to_string();
+        if is_exception {
+            if positive.is_empty() {
+                self.global_exceptions.insert(selector);
+            } else {
+                for d in positive {
+                    self.exceptions.insert((d, selector.clone()));
+                }
+            }
+        } else {
+            if positive.is_empty() {
+                self.hide.insert((String::new(), selector.clone()));
+            }
+            for d in positive {
+                self.hide.insert((d, selector.clone()));
+            }
+            for d in negative {
+                self.exceptions.insert((d, selector.clone()));
+            }
+        }
+        true
+    }
+
+    pub fn rules_count(&self) -> usize {
+        self.hide.len()
+    }
+
+    /// Lines of the DAC cosmetic section
+    pub fn lines(&self) -> impl Iterator<Item = String> {
+        self.hide
+            .iter()
+            .filter(|(_, s)| !self.global_exceptions.contains(s))
+            .map(|(d, s)| format!("{d}\t{s}"))
+            .chain(
+                self.exceptions
+                    .iter()
+                    .filter(|(_, s)| !self.global_exceptions.contains(s))
+                    .map(|(d, s)| format!("~{d}\t{s}")),
+            )
+    }
+}
+
+fn simple_name(selector: &str, prefix: char) -> Option<&str> {
+    selector.strip_prefix(prefix).filter(|n| {
+        !n.is_empty()
+            && n.bytes()
+                .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+    })
+}
+
+/// Selector of the DAC with its text, parsed once at load
+struct CosmeticSelector {
+    text: String,
+    selector: Selector,
+}
+
+impl CosmeticSelector {
+    fn parse(text: &str) -> Result<Self, UnifiedError> {
+        Ok(Self {
+            text: text.to_string(),
+            selector: Selector::from_str(text)
+                .map_err(|e| format!("Invalid cosmetic selector '{text}' in DAC: {e}"))?,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct CosmeticFilters {
+    // generic `.class` and `#id` selectors are checked in the `*` handler, it is much cheaper than a huge selectors list
+    generic_classes: HashSet<String>,
+    generic_ids: HashSet<String>,
+    generic_selectors: Vec<CosmeticSelector>,
+    generic_joined: Option<Selector>,
+    domain_selectors: HashMap<String, Vec<CosmeticSelector>>,
+    domain_exceptions: HashMap<String, HashSet<String>>,
+}
+
+impl CosmeticFilters {
+    pub fn parse(section: &[u8]) -> Result<Self, UnifiedError> {
+        let mut filters = Self::default();
+        for line in str::from_utf8(section)?.lines() {
+            let Some((domain, selector)) = line.split_once('\t') else {
+                continue;
+            };
+            if let Some(domain) = domain.strip_prefix('~') {
+                filters
+                    .domain_exceptions
+                    .entry(domain.to_string())
+                    .or_default()
+                    .insert(selector.to_string());
+            } else if !domain.is_empty() {
+                filters
+                    .domain_selectors
+                    .entry(domain.to_string())
+                    .or_default()
+                    .push(CosmeticSelector::parse(selector)?);
+            } else if let Some(class) = simple_name(selector, '.') {
+                filters.generic_classes.insert(class.to_string());
+            } else if let Some(id) = simple_name(selector, '#') {
+                filters.generic_ids.insert(id.to_string());
+            } else {
+                filters
+                    .generic_selectors
+                    .push(CosmeticSelector::parse(selector)?);
+            }
+        }
+        if !filters.generic_selectors.is_empty() {
+            let joined = filters
+                .generic_selectors
+                .iter()
+                .map(|s| s.text.as_str())
+                .collect::<Vec<_>>()
+                .join(",");
+            filters.generic_joined = Some(
+                Selector::from_str(&joined)
+                    .map_err(|e| format!("Invalid cosmetic selectors in DAC: {e}"))?,
+            );
+        }
+        Ok(filters)
+    }
+
+    /// Rules applicable to the page of the `host`
+    pub fn for_host<'a>(&'a self, host: Option<&str>) -> PageCosmetic<'a> {
+        let mut excepted: HashSet<&str> = HashSet::new();
+        let mut specific: Vec<&CosmeticSelector> = Vec::new();
+        if let Some(host) = host {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            for d in psl::host_and_parents(&host) {
+                if let Some(e) = self.domain_exceptions.get(d) {
+                    excepted.extend(e.iter().map(String::as_str));
+                }
+                if let Some(s) = self.domain_selectors.get(d) {
+                    specific.extend(s);
+                }
+            }
+        }
+
+        // без исключений берется готовый общий селектор, иначе один селектор собирается из оставшихся
+        let mut selectors: Vec<Cow<'a, Selector>> = Vec::new();
+        if self
+            .generic_selectors
+            .iter()
+            .any(|s| excepted.contains(s.text.as_str()))
+        {
+            let joined = self
+                .generic_selectors
+                .iter()
+                .filter(|s| !excepted.contains(s.text.as_str()))
+                .map(|s| s.text.as_str())
+                .collect::<Vec<_>>()
+                .join(",");
+            // каждый селектор уже разобран при загрузке, так что список из них тоже разберется
+            if !joined.is_empty()
+                && let Ok(s) = Selector::from_str(&joined)
+            {
+                selectors.push(Cow::Owned(s));
+            }
+        } else if let Some(s) = &self.generic_joined {
+            selectors.push(Cow::Borrowed(s));
+        }
+
+        specific.sort_unstable_by(|a, b| a.text.cmp(&b.text));
+        specific.dedup_by(|a, b| a.text == b.text);
+        selectors.extend(
+            specific
+                .into_iter()
+                .filter(|s| !excepted.contains(s.text.as_str()))
+                .map(|s| Cow::Borrowed(&s.selector)),
+        );
+
+        PageCosmetic {
+            filters: self,
+            excepted,
+            selectors,
+        }
+    }
+}
+
+pub struct PageCosmetic<'a> {
+    filters: &'a CosmeticFilters,
+    excepted: HashSet<&'a str>,
+    /// Selectors of elements to remove
+    pub selectors: Vec<Cow<'a, Selector>>,
+}
+
+impl PageCosmetic<'_> {
+    /// Check raw `id` and `class` attribute values against generic `#id` and `.class` rules
+    pub fn is_generic_hidden(&self, id: Option<&[u8]>, class: Option<&[u8]>) -> bool {
+        let is_hidden = |set: &HashSet<String>, prefix: char, name: &[u8]| {
+            str::from_utf8(name).is_ok_and(|name| {
+                set.contains(name)
+                    && (self.excepted.is_empty()
+                        || !self.excepted.contains(format!("{prefix}{name}").as_str()))
+            })
+        };
+        id.is_some_and(|id| is_hidden(&self.filters.generic_ids, '#', id))
+            || class.is_some_and(|class| {
+                class
+                    .split(u8::is_ascii_whitespace)
+                    .any(|c| !c.is_empty() && is_hidden(&self.filters.generic_classes, '.', c))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dac::psl::PS_LIST;
+    use lol_html::{
+        ElementContentHandlers, RewriteStrSettings, html_content::Element, rewrite_str,
+    };
+
+    fn filters(rules: &[&str]) -> CosmeticFilters {
+        let mut builder = CosmeticRulesBuilder::default();
+        for rule in rules {
+            assert!(builder.add_rule(rule), "{rule}");
+        }
+        CosmeticFilters::parse(builder.lines().collect::<Vec<_>>().join("\n").as_bytes()).unwrap()
+    }
+
+    // остаток страницы после удаления элементов по селекторам
+    fn hide(page: &PageCosmetic, html: &str) -> String {
+        let settings = RewriteStrSettings {
+            element_content_handlers: page
+                .selectors
+                .iter()
+                .map(|s| {
+                    (
+                        Cow::Borrowed(s.as_ref()),
+                        ElementContentHandlers::default().element(|el: &mut Element| {
+                            el.remove();
+                            Ok(())
+                        }),
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        };
+        rewrite_str(html, settings).unwrap()
+    }
+
+    #[test]
+    fn selectors_are_sorted_by_kind() {
+        let mut builder = CosmeticRulesBuilder::default();
+        assert!(!builder.add_rule("||ads.example.com^"));
+        assert!(builder.add_rule("example.com##+js(nowebrtc)"));
+        assert!(builder.add_rule("##div[[broken"));
+        assert!(builder.add_rule("example.com#$#body { color: red }"));
+        assert_eq!(builder.rules_count(), 0);
+
+        let f = filters(&[
+            "##.banner",
+            "###promo",
+            "##div.ad > span",
+            "example.com,~sub.example.com##aside",
+        ]);
+        assert!(f.generic_classes.contains("banner"));
+        assert!(f.generic_ids.contains("promo"));
+        assert_eq!(f.generic_selectors.len(), 1);
+        assert!(f.generic_joined.is_some());
+        assert_eq!(f.domain_selectors["example.com"].len(), 1);
+        assert!(f.domain_exceptions["sub.example.com"].contains("aside"));
+    }
+
+    #[test]
+    fn exceptions_remove_selectors_for_the_host() {
+        PS_LIST::init(&None).unwrap();
+        let f = filters(&[
+            "##.banner",
+            "##div.ad",
+            "##span.ad",
+            "##p.ad",
+            "example.com##aside",
+            "shop.example.com#@#div.ad",
+            "shop.example.com#@#.banner",
+            "~blog.example.com##aside",
+            "#@#p.ad",
+        ]);
+        let html = "<div class=ad>1</div><span class=ad>2</span><p class=ad>3</p><aside>4</aside>";
+
+        let other = f.for_host(Some("other.org"));
+        assert_eq!(other.selectors.len(), 1);
+        assert_eq!(hide(&other, html), "<p class=ad>3</p>");
+        assert!(other.is_generic_hidden(None, Some(b"x banner")));
+
+        let page = f.for_host(Some("www.example.com"));
+        assert_eq!(hide(&page, html), "<p class=ad>3</p>");
+
+        // оставшиеся после исключений общие селекторы собираются в один
+        let shop = f.for_host(Some("Shop.Example.com."));
+        assert_eq!(shop.selectors.len(), 2);
+        assert_eq!(hide(&shop, html), "<div class=ad>1</div><p class=ad>3</p>");
+        assert!(!shop.is_generic_hidden(None, Some(b"banner")));
+
+        let blog = f.for_host(Some("blog.example.com"));
+        assert_eq!(hide(&blog, html), "<p class=ad>3</p><aside>4</aside>");
+    }
+}
@@ -0,0 +1,65 @@
+use crate::dac::is_subdomain_or_equal;
+use std::fmt;
+
+/// Domains of the `$domain=` option or of the cosmetic rule (subdomains are included)
+#[derive(Default, Clone)]
+pub struct DomainList {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl DomainList {
+    /// `None` if the rule must be skipped: all its positive entries are unsupported (`example.*`, `/regex/`),
+    /// so it would become a generic one
+    pub fn parse(value: &str, separator: char) -> Option<Self> {
+        let mut list = Self::default();
+        let mut has_unsupported = false;
+        for d in value
+            .split(separator)
+            .map(str::trim_ascii)
+            .filter(|d| !d.is_empty())
+        {
+            let (d, is_negative) = match d.strip_prefix('~') {
+                Some(d) => (d, true),
+                None => (d, false),
+            };
+            if d.contains(['*', '/', '\t']) || !d.is_ascii() {
+                has_unsupported |= !is_negative;
+                continue;
+            }
+            let d = d.trim_matches('.').to_ascii_lowercase();
+            if is_negative {
+                list.exclude.push(d);
+            } else {
+                list.include.push(d);
+            }
+        }
+        (!has_unsupported || !list.include.is_empty()).then_some(list)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn is_match(&self, host: &str) -> bool {
+        !self.exclude.iter().any(|d| is_subdomain_or_equal(host, d))
+            && (self.include.is_empty()
+                || self.include.iter().any(|d| is_subdomain_or_equal(host, d)))
+    }
+}
+
+/// Same format as the `$domain=` option: `example.com|~sub.example.com`
+impl fmt::Display for DomainList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut sep = "";
+        for d in &self.include {
+            write!(f, "{sep}{d}")?;
+            sep = "|";
+        }
+        for d in &self.exclude {
+            write!(f, "{sep}~{d}")?;
+            sep = "|";
+        }
+        Ok(())
+    }
+}
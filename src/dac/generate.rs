@@ -1,14 +1,21 @@
 use crate::{
     cli::Commands,
     dac::{
-        pattern_type::PatternType,
-        patterns_map::{INNER_SUBDOMAIN_BLACKLIST, PatternsMap},
+        DAC_HEADER, SectionKind,
+        cosmetic::CosmeticRulesBuilder,
+        domains::DomainList,
+        pattern_type::{PatternType, pattern_index, rule_text, unpack_value},
+        patterns_map::{INNER_SUBDOMAIN_BLACKLIST, MIN_CONDITIONAL_KEY_LEN, PatternsMap},
         psl::prepare_adblock_filter,
+        regex_rule,
+        resource_type::ResourceTypes,
+        wildcard::key_fragment,
+        write_section,
     },
     maybe::UnifiedError,
 };
 use daachorse::DoubleArrayAhoCorasick;
-use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
 use std::path::PathBuf;
@@ -29,14 +36,21 @@ fn is_valid_domain_part_with_dot(s: &str) -> bool {
     b[0] != b'-' && b[n - 2] != b'-'
 }
 
-fn is_valid_script_rule(options: &str) -> Option<bool> {
-    let mut has_positive_types = false;
-    let mut script_allowed = false;
+struct RuleOptions {
+    third_party: bool,
+    domains: Option<DomainList>,
+    types: ResourceTypes,
+}
+
+fn parse_rule_options(options: &str) -> Option<RuleOptions> {
+    let mut positive_types = ResourceTypes::NONE;
+    let mut negative_types = ResourceTypes::NONE;
 
     let mut get_allowed = false;
     let mut has_positive_methods = false;
 
     let mut has_third_party = false;
+    let mut domains = None;
 
     for opt in options.split(',').map(str::trim_ascii) {
         if opt.is_empty() {
@@ -49,9 +63,9 @@ fn is_valid_script_rule(options: &str) -> Option<bool> {
         };
 
         match key.as_ref() {
-            "~script"
-            | "~all"
-            | "badfilter"
+            "badfilter"
+            | "document"
+            | "doc"
             | "~third-party"
             | "~3p"
             | "~strict3p"
@@ -79,16 +93,17 @@ fn is_valid_script_rule(options: &str) -> Option<bool> {
             | "cookie"
             | "popup"
             | "popunder"
+            | "elemhide"
+            | "ehide"
+            | "generichide"
+            | "ghide"
+            | "specifichide"
+            | "shide"
+            | "genericblock"
             | "match-case" => return None,
             "domain" | "from" => {
                 if let Some(v) = value {
-                    if !v.is_empty()
-                        && v.split('|')
-                            .map(str::trim_ascii)
-                            .any(|d| !d.is_empty() && !d.starts_with('~'))
-                    {
-                        return None;
-                    }
+                    domains = Some(DomainList::parse(v, '|')?).filter(|l| !l.is_empty());
                 }
                 continue;
             }
@@ -121,23 +136,32 @@ fn is_valid_script_rule(options: &str) -> Option<bool> {
                 continue;
             }
             "third-party" | "3p" | "strict3p" | "strict-third-party" => has_third_party = true,
-            "script" | "all" => {
-                has_positive_types = true;
-                script_allowed = true;
+            _ => {
+                if let Some(t) = key.strip_prefix('~').and_then(ResourceTypes::from_option) {
+                    negative_types = negative_types | t;
+                } else if let Some(t) = ResourceTypes::from_option(&key) {
+                    positive_types = positive_types | t;
+                }
             }
-            "image" | "css" | "stylesheet" | "frame" | "subdocument" | "document" | "media"
-            | "font" | "ping" | "websocket" | "other" | "object" | "webrtc" | "csp_report"
-            | "xmlhttprequest" | "xhr" => has_positive_types = true,
-            _ => {}
         }
     }
-    if has_positive_types && !script_allowed {
+    // без положительных типов правило применяется ко всем, кроме исключённых через ~
+    let types = if positive_types == ResourceTypes::NONE {
+        ResourceTypes::ALL
+    } else {
+        positive_types
+    } & !negative_types;
+    if types == ResourceTypes::NONE {
         return None;
     }
     if has_positive_methods && !get_allowed {
         return None;
     }
-    Some(has_third_party)
+    Some(RuleOptions {
+        third_party: has_third_party,
+        domains,
+        types,
+    })
 }
 
 pub fn run(args: &Commands) -> Result<(), UnifiedError> {
@@ -145,7 +169,14 @@ pub fn run(args: &Commands) -> Result<(), UnifiedError> {
         Commands::Dacgen { dump, inputs, dac } => {
             let dash_path = PathBuf::from("-");
 
-            let mut patterns: BTreeMap<String, u32> = BTreeMap::new();
+            let mut patterns = PatternsMap::default();
+            let mut cosmetic = CosmeticRulesBuilder::default();
+            // pattern index -> $domain= of the rule
+            let mut pattern_domains: HashMap<u32, DomainList> = HashMap::new();
+            // pattern index -> full pattern with `*`
+            let mut pattern_wildcards: HashMap<u32, String> = HashMap::new();
+            // pattern index -> regex of the /regex/ rule
+            let mut pattern_regexes: HashMap<u32, String> = HashMap::new();
 
             for input in inputs {
                 let reader: Box<dyn BufRead> = if *input == dash_path {
@@ -161,36 +192,67 @@ pub fn run(args: &Commands) -> Result<(), UnifiedError> {
                     if line.is_empty()
                         || line.starts_with('!')
                         || line.starts_with('[')
-                        || line.starts_with("@@")
-                        || line.contains("##")
-                        || line.contains("#@#")
-                        || line.contains("#?#")
+                        || cosmetic.add_rule(line)
                     {
                         continue;
                     }
 
+                    let is_exception = line.starts_with("@@");
+                    if is_exception {
+                        line = &line[2..];
+                    }
+
                     let mut third_party = false;
+                    let mut domains = None;
+                    let mut types = ResourceTypes::ALL;
                     //ищем из конца в начало до $ или / , но отрезаем только часть после $ включительно
-                    if let Some(pos) = line.rfind(|c: char| c == '/' || c == '$') {
-                        if line.as_bytes()[pos] == b'$' {
-                            if pos < 1 {
-                                continue;
-                            }
-                            let Some(_third_party) = is_valid_script_rule(&line[pos + 1..]) else {
-                                continue;
-                            };
-                            third_party = _third_party;
-                            line = &line[..pos]
+                    if let Some(pos) = line.rfind(['/', '$'])
+                        && line.as_bytes()[pos] == b'$'
+                    {
+                        if pos < 1 {
+                            continue;
                         }
+                        let Some(options) = parse_rule_options(&line[pos + 1..]) else {
+                            continue;
+                        };
+                        third_party = options.third_party;
+                        domains = options.domains;
+                        types = options.types;
+                        line = &line[..pos]
                     }
 
-                    // drop types:
-                    // - /regexp/
-                    // - ends_with|
-                    if (line.ends_with('/') && line.starts_with('/')) || line.ends_with('|') {
+                    let flags = |ptype: PatternType| {
+                        ptype
+                            .with_third_party(third_party)
+                            .with_exception(is_exception)
+                            .with_domain_scoped(domains.is_some())
+                    };
+
+                    if line.len() > 2 && line.ends_with('/') && line.starts_with('/') {
+                        let regex = &line[1..line.len() - 1];
+                        if let Some(key) = regex_rule::required_literal(regex).filter(|k| {
+                            k.len() >= MIN_CONDITIONAL_KEY_LEN
+                                && !regex.contains(['\t', '\n'])
+                                && regex_rule::compile(regex).is_ok()
+                        }) {
+                            let ptype = flags(PatternType::Substring).with_regex(true);
+                            let v = patterns.add_pattern(ptype, types, key);
+                            let idx = pattern_index(unpack_value(v).0);
+                            if let Some(d) = &domains {
+                                pattern_domains.insert(idx, d.clone());
+                            }
+                            pattern_regexes.insert(idx, regex.to_string());
+                        }
                         continue;
                     }
 
+                    // `|` в конце - привязка к концу URL
+                    let mut end_anchored = false;
+                    if line.len() > 1 && line.ends_with('|') {
+                        line = &line[..line.len() - 1];
+                        end_anchored = !line.ends_with('*');
+                    }
+
                     let mut exist_caret_at_end = false;
                     loop {
                         if line.ends_with('^') {
@@ -200,15 +262,43 @@ pub fn run(args: &Commands) -> Result<(), UnifiedError> {
                         }
                         line = &line[..line.len() - 1];
                     }
+                    line = line.trim_start_matches('*');
 
-                    if line.is_empty() || line.contains('*') /* '*' is supported only at end */ || line.contains('^')
+                    if line.is_empty() || line.contains('^')
                     /* в easylist есть ошибки в фильтрах, поэтому если '^' все еще присутсвует, то это битое правило */ || !line.is_ascii()
                     {
                         continue;
                     }
 
+                    // исключения и правила с условиями не должны порождать общие блокирующие паттерны
+                    let is_plain_block = !is_exception
+                        && domains.is_none()
+                        && types == ResourceTypes::ALL
+                        && !end_anchored
+                        && !line.contains('*');
+                    macro_rules! add_pattern {
+                        ($ptype:expr, $value:expr) => {
+                            let value: String = $value;
+                            let (_, key) = key_fragment(&value);
+                            let is_wildcard = key.len() < value.len();
+                            if !is_wildcard || key.len() >= MIN_CONDITIONAL_KEY_LEN {
+                                let ptype = flags($ptype)
+                                    .with_wildcard(is_wildcard)
+                                    .with_end_anchored(end_anchored);
+                                let v = patterns.add_pattern(ptype, types, key.to_string());
+                                let idx = pattern_index(unpack_value(v).0);
+                                if let Some(d) = &domains {
+                                    pattern_domains.insert(idx, d.clone());
+                                }
+                                if is_wildcard {
+                                    pattern_wildcards.insert(idx, value.to_ascii_lowercase());
+                                }
+                            }
+                        };
+                    }
+
                     if let Some(substr) = line.strip_prefix("://") {
-                        if is_valid_domain_part_with_dot(substr) {
+                        if is_plain_block && is_valid_domain_part_with_dot(substr) {
                             unsafe {
                                 #[allow(static_mut_refs)]
                                 INNER_SUBDOMAIN_BLACKLIST
@@ -216,80 +306,78 @@ pub fn run(args: &Commands) -> Result<(), UnifiedError> {
                             }
                             patterns.add_pattern(
                                 PatternType::AnyDomainPartBeforeETLD,
+                                types,
                                 substr.to_string(),
                             );
                         } else {
-                            patterns.add_pattern(
-                                PatternType::SlashedStart.with_third_party(third_party),
-                                line[1..].to_string(),
-                            );
+                            add_pattern!(PatternType::SlashedStart, line[1..].to_string());
                         }
-                    } else if line.starts_with('|') {
+                    } else if let Some(anchored) = line.strip_prefix('|') {
                         // Обработка якорей | и ||
-                        let (content, domain_starts_with) = if let Some(c) = line.strip_prefix("||")
-                        {
-                            (c, false)
-                        } else {
-                            (&line[1..], true) // strip_prefix('|')
+                        let (content, domain_starts_with) = match anchored.strip_prefix('|') {
+                            Some(c) => (c, false),
+                            None => (anchored, true),
                         };
 
                         if let Some(filter) = prepare_adblock_filter(content) {
-                            if patterns.add_shared_subdomain_pattern(filter.sub_without_www) {
+                            if filter.domain.contains('*') {
+                                continue;
+                            }
+                            if is_plain_block
+                                && patterns.add_shared_subdomain_pattern(filter.sub_without_www)
+                            {
                                 continue;
                             }
 
                             let mut suffix = filter.suffix.replace('^', "/"); // для упрощения считаем разделитель слешем 
-                            if exist_caret_at_end && !suffix.ends_with('/') {
+                            // `^|` - разделитель может совпасть с концом URL
+                            if exist_caret_at_end && !end_anchored && !suffix.ends_with('/') {
                                 suffix = format!("{suffix}/")
                             }
 
                             if domain_starts_with {
-                                patterns.add_pattern(
-                                    PatternType::SlashedStart.with_third_party(third_party),
-                                    format!("//{}{suffix}", filter.domain),
+                                add_pattern!(
+                                    PatternType::SlashedStart,
+                                    format!("//{}{suffix}", filter.domain)
                                 );
                             } else {
                                 // всегда проверяем, что присуттвует хоть один слеш в суффиксе, чтобы привязаться к концу домена
                                 // suffix может не содержать / только если он пустой, ибо split_adblock_filter режет по /
                                 if suffix.is_empty() {
-                                    suffix.push_str("/");
+                                    suffix.push('/');
                                 }
                                 if let Some(etld_2) = filter.etld_plus_2_without_www {
-                                    patterns.add_pattern(
-                                        PatternType::DomainEndWithDotPrefix
-                                            .with_third_party(third_party),
-                                        format!(".{etld_2}{suffix}"),
+                                    add_pattern!(
+                                        PatternType::DomainEndWithDotPrefix,
+                                        format!(".{etld_2}{suffix}")
                                     );
                                 } else {
-                                    patterns.add_pattern(
-                                        PatternType::DomainEnd.with_third_party(third_party),
-                                        format!("{}{suffix}", filter.domain),
+                                    add_pattern!(
+                                        PatternType::DomainEnd,
+                                        format!("{}{suffix}", filter.domain)
                                     );
                                 }
                             }
                         }
                     } else {
                         let line_fixed = line.replace('^', "/"); // для упрощения считаем разделитель слешем
-                        if !prepare_adblock_filter(&line_fixed).is_some_and(|f| {
-                            patterns.add_shared_subdomain_pattern(f.sub_without_www)
-                        }) {
-                            patterns.add_pattern(
-                                PatternType::Substring.with_third_party(third_party),
-                                line_fixed,
-                            );
+                        if !(is_plain_block
+                            && prepare_adblock_filter(&line_fixed).is_some_and(|f| {
+                                patterns.add_shared_subdomain_pattern(f.sub_without_www)
+                            }))
+                        {
+                            add_pattern!(PatternType::Substring, line_fixed);
                         }
                     }
                 }
             }
 
             //удаляем проскочивший мусор, что сломает clean overlapping
-            patterns.remove(&"".to_string());
-            patterns.remove(&"/".to_string());
-            patterns.remove(&".".to_string());
-            patterns.remove(&"./".to_string());
-            patterns.remove(&"//".to_string());
+            for junk in ["", "/", ".", "./", "//"] {
+                patterns.keys.remove(junk);
+            }
 
-            if patterns.is_empty() {
+            if patterns.keys.is_empty() {
                 return Err("No patterns found.".into());
             }
 
@@ -306,56 +394,140 @@ pub fn run(args: &Commands) -> Result<(), UnifiedError> {
                 }};
             }
 
-            let mut aho_corasik: DoubleArrayAhoCorasick<u32> = timeit!(
+            // в DAC первое правило ключа, остальные в секции Alternatives
+            let mut aho_corasik: DoubleArrayAhoCorasick<u64> = timeit!(
                 {
                     DoubleArrayAhoCorasick::with_values(
-                        patterns.iter().map(|(k, v)| (k.clone(), *v)),
+                        patterns.keys.iter().map(|(k, v)| (k.clone(), v[0])),
                     )
                 },
                 "Building DAC for {} patterns",
-                patterns.len()
+                patterns.keys.len()
             );
 
-            for (pat, idx) in patterns.clone() {
-                for res in aho_corasik.find_overlapping_iter(&pat) {
-                    if res.value() > idx {
-                        if PatternType::from(res.value()).is_match(&pat, res.start()) {
-                            patterns.remove(&pat);
-                            break;
-                        }
-                    }
+            let snapshot = patterns.keys.clone();
+            for (pat, values) in &snapshot {
+                // исключения не удаляем и не используем как перекрывающие, как и правила с $domain= и типами
+                if values
+                    .iter()
+                    .any(|v| PatternType::from(unpack_value(*v).0).is_exception())
+                {
+                    continue;
+                }
+                let idx = values.iter().map(|v| unpack_value(*v).0).max().unwrap_or(0);
+                let covered = aho_corasik.find_overlapping_iter(pat).any(|res| {
+                    snapshot[&pat[res.start()..res.end()]].iter().any(|v| {
+                        let (res_idx, res_types) = unpack_value(*v);
+                        res_idx > idx
+                            && PatternType::from(res_idx).is_plain_block()
+                            && res_types == ResourceTypes::ALL
+                            && PatternType::from(res_idx).is_match(
+                                pat,
+                                res.start(),
+                                res.end(),
+                                None,
+                            )
+                    })
+                });
+                if covered {
+                    patterns.keys.remove(pat);
                 }
             }
+            drop(snapshot);
 
             aho_corasik = timeit!(
                 {
                     DoubleArrayAhoCorasick::with_values(
-                        patterns.iter().map(|(k, v)| (k.clone(), *v)),
+                        patterns.keys.iter().map(|(k, v)| (k.clone(), v[0])),
                     )
                 },
                 "Rebuild DAC without overlapping for {} patterns",
-                patterns.len()
+                patterns.keys.len()
             );
 
+            tracing::info!("Cosmetic rules: {}", cosmetic.rules_count());
+            let mut cosmetic_section = String::new();
+            for l in cosmetic.lines() {
+                cosmetic_section.push_str(&l);
+                cosmetic_section.push('\n');
+            }
+
+            let mut domains_section = String::new();
+            let mut wildcards_section = String::new();
+            let mut regexes_section = String::new();
+            let mut alternatives_section = String::new();
+            let pattern_line = |p: &str, v: u64| {
+                let (v32, _) = unpack_value(v);
+                let (ptype, idx) = (PatternType::from(v32), pattern_index(v32));
+                let domains = ptype
+                    .is_domain_scoped()
+                    .then(|| pattern_domains.get(&idx))
+                    .flatten();
+                let glob = ptype
+                    .is_wildcard()
+                    .then(|| pattern_wildcards.get(&idx).cloned())
+                    .flatten();
+                let regex = ptype
+                    .is_regex()
+                    .then(|| pattern_regexes.get(&idx).map(|r| format!("/{r}/")))
+                    .flatten();
+                rule_text(p, v, glob.or(regex).as_deref(), domains)
+            };
+            for values in patterns.keys.values() {
+                if let Some((first, rest)) = values.split_first()
+                    && !rest.is_empty()
+                {
+                    let rest: Vec<String> = rest.iter().map(u64::to_string).collect();
+                    alternatives_section.push_str(&format!(
+                        "{}\t{}\n",
+                        pattern_index(unpack_value(*first).0),
+                        rest.join(",")
+                    ));
+                }
+            }
+            for (_, v) in patterns.rules() {
+                let (v, _) = unpack_value(v);
+                let (ptype, idx) = (PatternType::from(v), pattern_index(v));
+                if ptype.is_domain_scoped()
+                    && let Some(d) = pattern_domains.get(&idx)
+                {
+                    domains_section.push_str(&format!("{idx}\t{d}\n"));
+                }
+                if ptype.is_wildcard()
+                    && let Some(glob) = pattern_wildcards.get(&idx)
+                {
+                    wildcards_section.push_str(&format!("{idx}\t{glob}\n"));
+                }
+                if ptype.is_regex()
+                    && let Some(regex) = pattern_regexes.get(&idx)
+                {
+                    regexes_section.push_str(&format!("{idx}\t{regex}\n"));
+                }
+            }
+
             let mut hasher = Xxh3::with_seed(0);
             if let Some(output_list) = dump {
                 let writer: Box<dyn Write> = if *output_list == dash_path {
                     Box::new(io::stdout())
                 } else {
-                    Box::new(File::create(&output_list)?)
+                    Box::new(File::create(output_list)?)
                 };
                 let mut buffered_writer = BufWriter::new(writer);
-                for (p, _) in &patterns {
-                    hasher.update(p.as_bytes());
-                    writeln!(buffered_writer, "{}", p)?;
+                for (p, v) in patterns.rules() {
+                    let line = pattern_line(p, v);
+                    hasher.update(line.as_bytes());
+                    writeln!(buffered_writer, "{}", line)?;
                 }
+                buffered_writer.write_all(cosmetic_section.as_bytes())?;
                 buffered_writer.flush()?;
             } else {
-                for (p, _) in &patterns {
-                    hasher.update(p.as_bytes());
+                for (p, v) in patterns.rules() {
+                    hasher.update(pattern_line(p, v).as_bytes());
                 }
             }
+            hasher.update(cosmetic_section.as_bytes());
 
+            let pattern_count = patterns.rules().count() as u64;
             drop(patterns);
             let h3: u64 = hasher.digest();
 
@@ -363,17 +535,48 @@ pub fn run(args: &Commands) -> Result<(), UnifiedError> {
                 let mut writer: Box<dyn Write> = if *dac == dash_path {
                     Box::new(io::stdout())
                 } else {
-                    Box::new(File::create(&dac)?)
+                    Box::new(File::create(dac)?)
                 };
-                writer.write_all(&[b'D', b'A', b'C', 1])?;
+                writer.write_all(&DAC_HEADER)?;
                 writer.write_all(&h3.to_le_bytes())?;
-                writer.write_all(&aho_corasik.serialize())?;
+                write_section(&mut writer, SectionKind::Patterns, &aho_corasik.serialize())?;
+                write_section(
+                    &mut writer,
+                    SectionKind::Cosmetic,
+                    cosmetic_section.as_bytes(),
+                )?;
+                write_section(
+                    &mut writer,
+                    SectionKind::Domains,
+                    domains_section.as_bytes(),
+                )?;
+                write_section(
+                    &mut writer,
+                    SectionKind::Wildcards,
+                    wildcards_section.as_bytes(),
+                )?;
+                write_section(
+                    &mut writer,
+                    SectionKind::Regexes,
+                    regexes_section.as_bytes(),
+                )?;
+                write_section(
+                    &mut writer,
+                    SectionKind::Alternatives,
+                    alternatives_section.as_bytes(),
+                )?;
+                write_section(
+                    &mut writer,
+                    SectionKind::PatternCount,
+                    &pattern_count.to_le_bytes(),
+                )?;
                 writer.flush()?;
             }
 
             tracing::info!("Done. Binary DAC: {}", dac.display());
 
             Ok(())
-        } //_ => Err("Unsupported command".into()),
+        }
+        _ => Err("Unsupported command".into()),
     }
 }
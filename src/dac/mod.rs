@@ -1,16 +1,35 @@
 use crate::{
-    dac::pattern_type::PatternType, initable_static, maybe,
-    processors::js_urls_iterator::JsUrlsIterator, resettable_lazy::ResettableLazy,
+    block_log::{self, BlockEvent},
+    dac::{
+        cosmetic::CosmeticFilters,
+        domains::DomainList,
+        pattern_type::{PatternType, pattern_index, rule_text, unpack_value},
+        resource_type::ResourceTypes,
+        wildcard::Wildcard,
+    },
+    initable_static, maybe,
+    processors::js_urls_iterator::JsUrlsIterator,
+    resettable_lazy::ResettableLazy,
 };
 use daachorse::DoubleArrayAhoCorasick;
 use memmap2::Mmap;
-use std::{fs::File, path::PathBuf};
+use regex::Regex;
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+};
 use url::{Host, Url};
 
+pub mod cosmetic;
+pub mod domains;
 pub mod generate;
 pub mod pattern_type;
 pub mod patterns_map;
 pub mod psl;
+pub mod regex_rule;
+pub mod resource_type;
+pub mod wildcard;
 
 const fn mix_version_into_hash(body_hash: u64) -> u64 {
     const APP_HASH: u64 =
@@ -23,34 +42,167 @@ const fn mix_version_into_hash(body_hash: u64) -> u64 {
             .wrapping_add(body_hash >> 2))
 }
 
-initable_static! {
-    DAC = |path: &PathBuf| -> Result<(String, DoubleArrayAhoCorasick<u32>), maybe::UnifiedError> {
-        let file = File::open(path)?;
-        unsafe {
-            let mmap = Mmap::map(&file)?;
-            if mmap.len() < 20 {
-                return Err("DAC file too short".into());
-            }
-            if !mmap[0..4].eq(&[b'D', b'A', b'C', 1]) {
-                return Err("Invalid DAC file format version".into());
-            }
-            let h3 = mix_version_into_hash(u64::from_le_bytes(mmap[4..12].try_into()?));
-            use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+/// `DAC` + format version, then xxh3 of the content and sections `[kind: u32 LE][len: u64 LE][data]`
+pub const DAC_HEADER: [u8; 4] = [b'D', b'A', b'C', 3];
 
-            Ok((format!("zhlob~{}~", URL_SAFE_NO_PAD.encode(h3.to_le_bytes())), DoubleArrayAhoCorasick::<u32>::deserialize_unchecked(&mmap[12..]).0))
+#[repr(u32)]
+pub enum SectionKind {
+    Patterns = 1,
+    Cosmetic = 2,
+    Domains = 3,
+    Wildcards = 4,
+    Regexes = 5,
+    /// Number of patterns, u64 LE
+    PatternCount = 6,
+    /// Other rules with the key of a DAC value: `index\tvalue,value`
+    Alternatives = 7,
+}
+
+pub fn write_section(
+    writer: &mut impl std::io::Write,
+    kind: SectionKind,
+    data: &[u8],
+) -> std::io::Result<()> {
+    writer.write_all(&(kind as u32).to_le_bytes())?;
+    writer.write_all(&(data.len() as u64).to_le_bytes())?;
+    writer.write_all(data)
+}
+
+pub struct Dac {
+    /// xxh3 of the patterns list mixed with the app version
+    pub hash: u64,
+    /// Values are `pattern_type::pack_value` of the rule
+    pub patterns: DoubleArrayAhoCorasick<u64>,
+    pub cosmetic: CosmeticFilters,
+    /// `$domain=` of the `DomainScoped` patterns by pattern index
+    pub domains: HashMap<u32, DomainList>,
+    /// Full patterns of the `Wildcard` patterns by pattern index
+    pub wildcards: HashMap<u32, Wildcard>,
+    /// Regexes of the `Regex` patterns by pattern index
+    pub regexes: HashMap<u32, Regex>,
+    /// Other rules sharing the key, by pattern index of the DAC value
+    pub alternatives: HashMap<u32, Vec<u64>>,
+    /// None for files generated without the count
+    pub pattern_count: Option<u64>,
+}
+
+fn parse_domains_section(data: &[u8]) -> Result<HashMap<u32, DomainList>, maybe::UnifiedError> {
+    let mut domains = HashMap::new();
+    for line in str::from_utf8(data)?.lines() {
+        if let Some((idx, list)) = line.split_once('\t') {
+            domains.insert(
+                idx.parse()?,
+                DomainList::parse(list, '|').ok_or("Invalid domains section in DAC")?,
+            );
         }
     }
+    Ok(domains)
 }
 
-pub fn is_match_code(code: &[u8], etld_1_info: &ResettableLazy<'_, Option<UrlBaseInfo>>) -> bool {
-    if DAC.get().is_some() {
-        for str in JsUrlsIterator::new(code) {
-            if is_match_src(str, etld_1_info) {
-                return true;
+fn parse_wildcards_section(data: &[u8]) -> Result<HashMap<u32, Wildcard>, maybe::UnifiedError> {
+    let mut wildcards = HashMap::new();
+    for line in str::from_utf8(data)?.lines() {
+        if let Some((idx, glob)) = line.split_once('\t') {
+            wildcards.insert(idx.parse()?, Wildcard::parse(glob));
+        }
+    }
+    Ok(wildcards)
+}
+
+fn parse_regexes_section(data: &[u8]) -> Result<HashMap<u32, Regex>, maybe::UnifiedError> {
+    let mut regexes = HashMap::new();
+    for line in str::from_utf8(data)?.lines() {
+        if let Some((idx, regex)) = line.split_once('\t') {
+            regexes.insert(idx.parse()?, regex_rule::compile(regex)?);
+        }
+    }
+    Ok(regexes)
+}
+
+fn parse_alternatives_section(data: &[u8]) -> Result<HashMap<u32, Vec<u64>>, maybe::UnifiedError> {
+    let mut alternatives = HashMap::new();
+    for line in str::from_utf8(data)?.lines() {
+        if let Some((idx, values)) = line.split_once('\t') {
+            let values = values
+                .split(',')
+                .map(str::parse)
+                .collect::<Result<Vec<u64>, _>>()?;
+            alternatives.insert(idx.parse()?, values);
+        }
+    }
+    Ok(alternatives)
+}
+
+initable_static! {
+    DAC = reloadable |path: &PathBuf| -> Result<Dac, maybe::UnifiedError> {
+        Dac::load(path)
+    }
+}
+
+impl Dac {
+    pub fn load(path: &Path) -> Result<Dac, maybe::UnifiedError> {
+        let file = File::open(path)?;
+        let mmap = unsafe { Mmap::map(&file)? };
+        if mmap.len() < 12 {
+            return Err("DAC file too short".into());
+        }
+        if !mmap[0..4].eq(&DAC_HEADER) {
+            return Err("Invalid DAC file format version, regenerate it with `dacgen`".into());
+        }
+        let hash = mix_version_into_hash(u64::from_le_bytes(mmap[4..12].try_into()?));
+
+        let mut patterns = None;
+        let mut cosmetic = CosmeticFilters::default();
+        let mut domains = HashMap::new();
+        let mut wildcards = HashMap::new();
+        let mut regexes = HashMap::new();
+        let mut alternatives = HashMap::new();
+        let mut pattern_count = None;
+        let mut rest = &mmap[12..];
+        while !rest.is_empty() {
+            if rest.len() < 12 {
+                return Err("DAC file is truncated".into());
+            }
+            let kind = u32::from_le_bytes(rest[0..4].try_into()?);
+            let len = usize::try_from(u64::from_le_bytes(rest[4..12].try_into()?))?;
+            let Some(data) = rest.get(12..12 + len) else {
+                return Err("DAC file is truncated".into());
+            };
+            match kind {
+                k if k == SectionKind::Patterns as u32 => {
+                    patterns = Some(unsafe {
+                        DoubleArrayAhoCorasick::<u64>::deserialize_unchecked(data).0
+                    });
+                }
+                k if k == SectionKind::Cosmetic as u32 => cosmetic = CosmeticFilters::parse(data)?,
+                k if k == SectionKind::Domains as u32 => domains = parse_domains_section(data)?,
+                k if k == SectionKind::Wildcards as u32 => {
+                    wildcards = parse_wildcards_section(data)?
+                }
+                k if k == SectionKind::Regexes as u32 => regexes = parse_regexes_section(data)?,
+                k if k == SectionKind::PatternCount as u32 => {
+                    pattern_count = Some(u64::from_le_bytes(data.try_into()?))
+                }
+                k if k == SectionKind::Alternatives as u32 => {
+                    alternatives = parse_alternatives_section(data)?
+                }
+                // секции из более новых версий пропускаем
+                _ => {}
             }
+            rest = &rest[12 + len..];
         }
+
+        Ok(Dac {
+            hash,
+            patterns: patterns.ok_or("DAC file has no patterns section")?,
+            cosmetic,
+            domains,
+            wildcards,
+            regexes,
+            alternatives,
+            pattern_count,
+        })
     }
-    false
 }
 
 fn is_subdomain_or_equal(host: &str, etld_plus1: &str) -> bool {
@@ -62,48 +214,248 @@ fn is_subdomain_or_equal(host: &str, etld_plus1: &str) -> bool {
 
 #[derive(Clone)]
 pub struct UrlBaseInfo {
+    /// Host of the page, `$domain=` options are checked against it
+    pub host: String,
     pub etld_plus1: String,
     pub base: Url,
 }
 
-pub fn is_match_src<'a>(
-    src: &'a [u8],
-    etld_1_info: &ResettableLazy<'_, Option<UrlBaseInfo>>,
-) -> bool {
-    if let Some((_, dac)) = DAC.get() {
-        unsafe {
-            println!("{}", str::from_utf8_unchecked(src));
+impl Dac {
+    /// `code` is removed as a whole, so its size is logged as saved
+    pub fn is_match_code(
+        &self,
+        code: &[u8],
+        etld_1_info: &ResettableLazy<'_, Option<UrlBaseInfo>>,
+    ) -> bool {
+        for str in JsUrlsIterator::new(code) {
+            if self.is_match_src(str, etld_1_info, ResourceTypes::SCRIPT, code.len()) {
+                return true;
+            }
         }
+        false
+    }
+
+    pub fn is_match_src(
+        &self,
+        src: &[u8],
+        etld_1_info: &ResettableLazy<'_, Option<UrlBaseInfo>>,
+        resource_type: ResourceTypes,
+        bytes_saved: usize,
+    ) -> bool {
+        tracing::trace!(src = %String::from_utf8_lossy(src), "check");
         //todo check join with url without protocols like //google.com
         if let Some(url_info) = etld_1_info.get() {
             let Some(url) = maybe!(url_info.base.join(str::from_utf8(src)?)?) else {
                 return false;
             };
-            let Some(host) = url.host() else {
-                return false;
-            };
-            match host {
-                Host::Domain(host) => {
-                    let src = url.as_str().to_ascii_lowercase();
-
-                    for m in dac.find_overlapping_iter(&src) {
-                        let p = PatternType::from(m.value());
-                        if p.is_match(&src, m.start())
-                            && (!p.is_third_party()
-                                || !is_subdomain_or_equal(host, &url_info.etld_plus1))
-                        {
-                            println!(
-                                "blocked by rule: {:?} {}",
-                                p,
-                                src[m.start()..m.end()].to_string()
-                            );
-                            return true;
-                        }
-                    }
+            return self.is_match_url(&url, &url_info, resource_type, bytes_saved);
+        }
+        false
+    }
+
+    /// `page` - the document which requested the `url`, `bytes_saved` - size of the blocked content if known
+    pub fn is_match_url(
+        &self,
+        url: &Url,
+        page: &UrlBaseInfo,
+        resource_type: ResourceTypes,
+        bytes_saved: usize,
+    ) -> bool {
+        let Some(Host::Domain(host)) = url.host() else {
+            return false;
+        };
+        let src = url.as_str().to_ascii_lowercase();
+
+        let mut blocked_by = None;
+        for m in self.patterns.find_overlapping_iter(&src) {
+            let others = self
+                .alternatives
+                .get(&pattern_index(unpack_value(m.value()).0))
+                .map_or(&[][..], Vec::as_slice);
+            for &value in std::iter::once(&m.value()).chain(others) {
+                let (v, types) = unpack_value(value);
+                let p = PatternType::from(v);
+                if !types.intersects(resource_type) {
+                    continue;
                 }
-                _ => {}
+                let wildcard = p
+                    .is_wildcard()
+                    .then(|| self.wildcards.get(&pattern_index(v)))
+                    .flatten();
+                if !p.is_match(&src, m.start(), m.end(), wildcard)
+                    || (p.is_third_party() && is_subdomain_or_equal(host, &page.etld_plus1))
+                    || (p.is_domain_scoped()
+                        && !self
+                            .domains
+                            .get(&pattern_index(v))
+                            .is_some_and(|d| d.is_match(&page.host)))
+                    // regex запускаем только после совпадения его литерала и остальных проверок
+                    || (p.is_regex()
+                        && !self
+                            .regexes
+                            .get(&pattern_index(v))
+                            .is_some_and(|r| r.is_match(&src)))
+                {
+                    continue;
+                }
+                if p.is_exception() {
+                    tracing::debug!(
+                        url = %url,
+                        page = %page.base,
+                        rule = %self.rule_text(&src[m.start()..m.end()], value),
+                        "allowed"
+                    );
+                    return false;
+                }
+                blocked_by.get_or_insert((m.start()..m.end(), value));
             }
         }
+        let Some((key, v)) = blocked_by else {
+            return false;
+        };
+        block_log::record(BlockEvent {
+            time: block_log::now(),
+            url: url.to_string(),
+            page: page.base.to_string(),
+            rule: self.rule_text(&src[key], v),
+            pattern_type: PatternType::from(unpack_value(v).0).to_string(),
+            resource_type: resource_type.to_string(),
+            bytes_saved: bytes_saved as u64,
+        });
+        true
+    }
+
+    /// Text of the matched rule for logs
+    fn rule_text(&self, key: &str, v: u64) -> String {
+        let (v32, _) = unpack_value(v);
+        let (ptype, idx) = (PatternType::from(v32), pattern_index(v32));
+        let pattern = if ptype.is_wildcard() {
+            self.wildcards.get(&idx).map(|w| w.to_string())
+        } else if ptype.is_regex() {
+            self.regexes.get(&idx).map(|r| format!("/{}/", r.as_str()))
+        } else {
+            None
+        };
+        let domains = ptype
+            .is_domain_scoped()
+            .then(|| self.domains.get(&idx))
+            .flatten();
+        rule_text(key, v, pattern.as_deref(), domains)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli::Commands, dac::psl::PS_LIST};
+
+    fn compile(name: &str, rules: &str) -> Dac {
+        let dir = std::env::temp_dir().join(format!("zhlob-dac-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (list, dac) = (dir.join("list.txt"), dir.join("list.dac"));
+        std::fs::write(&list, rules).unwrap();
+        generate::run(&Commands::Dacgen {
+            dump: None,
+            inputs: vec![list],
+            dac: dac.clone(),
+        })
+        .unwrap();
+        let dac = Dac::load(&dac).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+        dac
+    }
+
+    fn page(host: &str) -> UrlBaseInfo {
+        UrlBaseInfo {
+            host: host.to_string(),
+            etld_plus1: host.to_string(),
+            base: Url::parse(&format!("https://{host}/")).unwrap(),
+        }
+    }
+
+    #[test]
+    fn domain_scoped_exception_shares_key_with_block() {
+        PS_LIST::init(&None).unwrap();
+        let dac = compile(
+            "exception",
+            "||example.com/ads/^\n@@||example.com/ads/^$domain=site.com\n",
+        );
+        assert_eq!(dac.pattern_count, Some(2));
+        let url = Url::parse("https://example.com/ads/banner.js").unwrap();
+        assert!(!dac.is_match_url(&url, &page("site.com"), ResourceTypes::SCRIPT, 0));
+        assert!(!dac.is_match_url(&url, &page("news.site.com"), ResourceTypes::SCRIPT, 0));
+        assert!(dac.is_match_url(&url, &page("other.com"), ResourceTypes::SCRIPT, 0));
+    }
+
+    #[test]
+    fn typed_exception_shares_key_with_block() {
+        PS_LIST::init(&None).unwrap();
+        let dac = compile(
+            "typed",
+            "||example.com/ads/^\n@@||example.com/ads/^$image\n",
+        );
+        let url = Url::parse("https://example.com/ads/banner.png").unwrap();
+        assert!(!dac.is_match_url(&url, &page("site.com"), ResourceTypes::IMAGE, 0));
+        assert!(dac.is_match_url(&url, &page("site.com"), ResourceTypes::SCRIPT, 0));
+    }
+
+    #[test]
+    fn wildcard_rule_checks_every_fragment() {
+        PS_LIST::init(&None).unwrap();
+        let dac = compile("wildcard", "||example.com/ads/*/banner*.gif\n");
+        let page = page("site.com");
+        let hit =
+            |u: &str| dac.is_match_url(&Url::parse(u).unwrap(), &page, ResourceTypes::IMAGE, 0);
+        assert!(hit("https://example.com/ads/v1/banner2.gif"));
+        assert!(hit("https://cdn.example.com/ads/v1/x/banner.gif?a=1"));
+        assert!(!hit("https://example.com/ads/banner.gif"));
+        assert!(!hit("https://example.com/ads/v1/banner2.png"));
+    }
+
+    #[test]
+    fn end_anchored_rule_matches_at_url_end() {
+        PS_LIST::init(&None).unwrap();
+        let dac = compile("end", "||example.com/track.js|\n");
+        let page = page("site.com");
+        let hit =
+            |u: &str| dac.is_match_url(&Url::parse(u).unwrap(), &page, ResourceTypes::SCRIPT, 0);
+        assert!(hit("https://example.com/track.js"));
+        assert!(hit("https://example.com/track.js?v=1"));
+        assert!(!hit("https://example.com/track.json"));
+    }
+
+    #[test]
+    fn regex_rule_runs_after_its_literal() {
+        PS_LIST::init(&None).unwrap();
+        let dac = compile("regex", "/\\/adframe[0-9]+\\//\n/(a|b)+/\n");
+        // у второго regex нет обязательного литерала, он пропускается
+        assert_eq!(dac.pattern_count, Some(1));
+        let page = page("site.com");
+        let hit = |u: &str| {
+            dac.is_match_url(
+                &Url::parse(u).unwrap(),
+                &page,
+                ResourceTypes::SUBDOCUMENT,
+                0,
+            )
+        };
+        assert!(hit("https://x.com/AdFrame12/a"));
+        assert!(!hit("https://x.com/adframe/a"));
+    }
+
+    #[test]
+    fn covered_keys_are_removed() {
+        PS_LIST::init(&None).unwrap();
+        let dac = compile(
+            "covered",
+            "adbanner/\n||example.com/adbanner/top.gif\n@@||example.com/adbanner/ok.gif\n",
+        );
+        // длинный ключ перекрыт общим правилом, исключение остается
+        assert_eq!(dac.pattern_count, Some(2));
+        let page = page("site.com");
+        let hit =
+            |u: &str| dac.is_match_url(&Url::parse(u).unwrap(), &page, ResourceTypes::IMAGE, 0);
+        assert!(hit("https://example.com/adbanner/top.gif"));
+        assert!(!hit("https://example.com/adbanner/ok.gif"));
     }
-    false
 }
@@ -1,4 +1,9 @@
-use crate::dac::psl::prepare_adblock_filter;
+use crate::dac::{
+    domains::DomainList,
+    psl::prepare_adblock_filter,
+    resource_type::ResourceTypes,
+    wildcard::{Wildcard, is_url_end},
+};
 use std::fmt;
 
 #[derive(PartialEq, PartialOrd, Clone, Copy)]
@@ -14,6 +19,55 @@ impl PatternType {
     pub const DomainEnd: PatternType = PatternType(3 << 28); //check [. or //] before
     pub const Substring: PatternType = PatternType(4 << 28);
     pub const AnyDomainPartBeforeETLD: PatternType = PatternType(5 << 28); //check [. or //] before and locate in part before etld (must be always thirdparty)
+    //
+    pub const Exception: PatternType = PatternType(1 << 27); // @@ правило, совпадение отменяет блокировку
+    pub const DomainScoped: PatternType = PatternType(1 << 26); // $domain= хранится в DAC в отдельной таблице по индексу
+    pub const Wildcard: PatternType = PatternType(1 << 25); // ключ - самый длинный фрагмент, полный шаблон в отдельной таблице по индексу
+    pub const EndAnchored: PatternType = PatternType(1 << 24); // `|` в конце: совпадение заканчивается в конце URL или перед ?/#
+    pub const Regex: PatternType = PatternType(1 << 23); // ключ - обязательный литерал /regex/, сам regex в отдельной таблице по индексу
+}
+
+const KIND_MASK: u32 = 7 << 28;
+const FLAGS_MASK: u32 = 0xFF800000;
+// флаги, сужающие правило относительно его ключа
+const CONDITIONS_MASK: u32 = PatternType::DomainScoped.0
+    | PatternType::Wildcard.0
+    | PatternType::EndAnchored.0
+    | PatternType::Regex.0;
+
+/// Index of the pattern (value without type and flags), the key of the side tables
+pub fn pattern_index(v: u32) -> u32 {
+    v & !FLAGS_MASK
+}
+
+/// DAC value: `ResourceTypes` of the rule in the high half, `PatternType` with the pattern index in the low half
+pub fn pack_value(v: u32, types: ResourceTypes) -> u64 {
+    ((types.0 as u64) << 32) | v as u64
+}
+
+pub fn unpack_value(v: u64) -> (u32, ResourceTypes) {
+    (v as u32, ResourceTypes((v >> 32) as u16))
+}
+
+/// Rule in adblock syntax as it is stored in DAC: `key` - DAC key, `pattern` - full glob or `/regex/` of the rule
+pub fn rule_text(key: &str, v: u64, pattern: Option<&str>, domains: Option<&DomainList>) -> String {
+    let (v, types) = unpack_value(v);
+    let ptype = PatternType::from(v);
+    let mut options = Vec::new();
+    if types != ResourceTypes::ALL {
+        options.push(types.to_string());
+    }
+    if let Some(d) = domains {
+        options.push(format!("domain={d}"));
+    }
+    format!(
+        "{}{}{}{}{}",
+        if ptype.is_exception() { "@@" } else { "" },
+        pattern.unwrap_or(key),
+        if ptype.is_end_anchored() { "|" } else { "" },
+        if options.is_empty() { "" } else { "$" },
+        options.join(",")
+    )
 }
 
 impl fmt::Display for PatternType {
@@ -24,6 +78,11 @@ impl fmt::Display for PatternType {
 
         for (mask, name) in [
             (Self::NotThirdParty.0, "NotThirdParty"),
+            (Self::Exception.0, "Exception"),
+            (Self::DomainScoped.0, "DomainScoped"),
+            (Self::Wildcard.0, "Wildcard"),
+            (Self::EndAnchored.0, "EndAnchored"),
+            (Self::Regex.0, "Regex"),
             (Self::AnyDomainPartBeforeETLD.0, "AnyDomainPartBeforeETLD"),
             (Self::DomainEnd.0, "DomainEnd"),
             (Self::DomainEndWithDotPrefix.0, "DomainEndWithDotPrefix"),
@@ -59,16 +118,89 @@ impl PatternType {
         }
     }
 
+    pub fn with_exception(self, exception: bool) -> PatternType {
+        self.with_flag(PatternType::Exception, exception)
+    }
+
+    pub fn with_domain_scoped(self, scoped: bool) -> PatternType {
+        self.with_flag(PatternType::DomainScoped, scoped)
+    }
+
+    pub fn with_wildcard(self, wildcard: bool) -> PatternType {
+        self.with_flag(PatternType::Wildcard, wildcard)
+    }
+
+    pub fn with_end_anchored(self, end_anchored: bool) -> PatternType {
+        self.with_flag(PatternType::EndAnchored, end_anchored)
+    }
+
+    pub fn with_regex(self, regex: bool) -> PatternType {
+        self.with_flag(PatternType::Regex, regex)
+    }
+
+    fn with_flag(self, flag: PatternType, on: bool) -> PatternType {
+        if on {
+            PatternType::from(self.0 | flag.0)
+        } else {
+            PatternType::from(self.0 & !flag.0)
+        }
+    }
+
     pub fn from(v: u32) -> Self {
-        PatternType(v & 0xF0000000)
+        PatternType(v & FLAGS_MASK)
     }
 
     pub fn is_third_party(self) -> bool {
         self.0 & PatternType::NotThirdParty.0 == 0
     }
 
-    pub fn is_match(self, src: &str, match_pos: usize) -> bool {
-        match self.with_third_party(true) {
+    pub fn is_exception(self) -> bool {
+        self.0 & PatternType::Exception.0 != 0
+    }
+
+    pub fn is_domain_scoped(self) -> bool {
+        self.0 & PatternType::DomainScoped.0 != 0
+    }
+
+    pub fn is_wildcard(self) -> bool {
+        self.0 & PatternType::Wildcard.0 != 0
+    }
+
+    pub fn is_end_anchored(self) -> bool {
+        self.0 & PatternType::EndAnchored.0 != 0
+    }
+
+    pub fn is_regex(self) -> bool {
+        self.0 & PatternType::Regex.0 != 0
+    }
+
+    /// Has conditions besides the key match: `$domain=`, wildcards, end anchor or regex
+    pub fn has_conditions(self) -> bool {
+        self.0 & CONDITIONS_MASK != 0
+    }
+
+    /// Blocking rule without conditions, so it can replace any narrower rule with the same or longer key
+    pub fn is_plain_block(self) -> bool {
+        !self.is_exception() && !self.has_conditions()
+    }
+
+    /// `start..end` - position of the DAC key in `src`, `wildcard` - full pattern for `Wildcard` patterns
+    pub fn is_match(
+        self,
+        src: &str,
+        start: usize,
+        end: usize,
+        wildcard: Option<&Wildcard>,
+    ) -> bool {
+        if self.is_wildcard() {
+            return wildcard.is_some_and(|w| w.is_match(self, src, start, end));
+        }
+        self.is_match_at(src, start) && (!self.is_end_anchored() || is_url_end(src, end))
+    }
+
+    /// Check of the pattern start
+    pub fn is_match_at(self, src: &str, match_pos: usize) -> bool {
+        match PatternType(self.0 & KIND_MASK) {
             PatternType::SlashedStart => {
                 match_pos == 0 || (match_pos > 0 && src.as_bytes()[match_pos - 1] == b':')
             }
@@ -1,10 +1,15 @@
-use crate::dac::pattern_type::PatternType;
-use easy_ext::ext;
+use crate::dac::{
+    pattern_type::{PatternType, pack_value, unpack_value},
+    resource_type::ResourceTypes,
+};
 use std::{
     cell::LazyCell,
     collections::{BTreeMap, HashSet},
 };
 
+/// Minimal length of the DAC key of wildcard and regex rules, shorter keys would hit almost any URL
+pub const MIN_CONDITIONAL_KEY_LEN: usize = 4;
+
 /*
 grep -E "^(\|\||\.)" blocklist.txt | sed -E 's#^(\|\||\.)([^.]+).*#\2#' | sort | uniq -c | sort -rn | head -n 100
 */
@@ -258,24 +263,63 @@ pub static mut INNER_SUBDOMAIN_BLACKLIST: LazyCell<HashSet<String>> = LazyCell::
     )
 });
 
-#[ext(PatternsMap)]
-pub impl BTreeMap<String, u32> {
-    fn add_pattern(&mut self, ptype: PatternType, mut value: String) {
-        let base_idx = 10 + (self.len() as u32);
+/// Rules by DAC key. Several rules can share a key (`||ads.example^` and `@@||ads.example^$domain=site.com`):
+/// the first one is the DAC value, the others are stored in the `Alternatives` section under its index.
+#[derive(Default, Clone)]
+pub struct PatternsMap {
+    pub keys: BTreeMap<String, Vec<u64>>,
+    next_index: u32,
+}
+
+impl PatternsMap {
+    /// Returns the stored value of the added rule or of the same rule with extended resource types
+    pub fn add_pattern(
+        &mut self,
+        ptype: PatternType,
+        types: ResourceTypes,
+        mut value: String,
+    ) -> u64 {
         // Сдвигаем на 29 бит влево.
         // В u32 останется: [B B B I I I I I I I I I I I I I I I I I I I I I I I I I I I]
         // Где B - биты типа, I - биты индекса.
+        // Старшие 32 бита u64 - маска типов ресурсов
         value.make_ascii_lowercase();
-        self.entry(value).or_insert(ptype.0 | base_idx);
+        let values = self.keys.entry(value).or_default();
+        // одинаковые правила без условий, отличающиеся только типами, объединяем
+        if !ptype.has_conditions()
+            && let Some(v) = values
+                .iter_mut()
+                .find(|v| PatternType::from(unpack_value(**v).0) == ptype)
+        {
+            let (old_v, old_types) = unpack_value(*v);
+            *v = pack_value(old_v, old_types | types);
+            return *v;
+        }
+        // правила с условиями храним все: у каждого свой индекс для $domain=, шаблона и regex
+        let v = pack_value(ptype.0 | (10 + self.next_index), types);
+        self.next_index += 1;
+        values.push(v);
+        v
+    }
+
+    /// Key and value of every rule
+    pub fn rules(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.keys
+            .iter()
+            .flat_map(|(k, values)| values.iter().map(move |v| (k.as_str(), *v)))
     }
 
-    fn add_shared_subdomain_pattern(&mut self, subdomain: &str) -> bool {
+    pub fn add_shared_subdomain_pattern(&mut self, subdomain: &str) -> bool {
         let mut exist = false;
         for sd in subdomain.to_ascii_lowercase().split('.') {
             unsafe {
                 #[allow(static_mut_refs)]
                 if INNER_SUBDOMAIN_BLACKLIST.contains(sd) {
-                    self.add_pattern(PatternType::AnyDomainPartBeforeETLD, format!("{sd}."));
+                    self.add_pattern(
+                        PatternType::AnyDomainPartBeforeETLD,
+                        ResourceTypes::ALL,
+                        format!("{sd}."),
+                    );
                     exist = true;
                 }
             }
@@ -3,20 +3,32 @@ use std::{borrow::Cow, net::IpAddr, path::PathBuf};
 use crate::{UnifiedError, initable_static};
 use publicsuffix2::{List, MatchOpts, TypeFilter, options::RAW_NORMALIZER};
 
+pub struct PublicSuffixList {
+    list: List,
+    /// File of the list, `stdin` or `built-in`
+    source: String,
+}
+
 initable_static! {
-    PS_LIST = |path:&Option<std::path::PathBuf>| -> Result<List, UnifiedError> {
-        Ok(if let Some(p) = path {
+    PS_LIST = reloadable |path:&Option<std::path::PathBuf>| -> Result<PublicSuffixList, UnifiedError> {
+        let (list, source) = if let Some(p) = path {
             if p == &PathBuf::from("-") {
-                List::parse(&std::io::read_to_string(std::io::stdin())?)?
+                (List::parse(&std::io::read_to_string(std::io::stdin())?)?, "stdin".to_string())
             } else {
-                List::from_file(p)?
+                (List::from_file(p)?, p.display().to_string())
             }
         } else {
-            List::default()
-        })
+            (List::default(), "built-in".to_string())
+        };
+        Ok(PublicSuffixList { list, source })
     };
 }
 
+/// File of the loaded Public Suffix List, `stdin` or `built-in`
+pub fn source() -> String {
+    PS_LIST::get().source.clone()
+}
+
 const ETLD_OPTS_RAW: MatchOpts = MatchOpts {
     wildcard: false,
     normalizer: Some(&RAW_NORMALIZER),
@@ -25,7 +37,21 @@ const ETLD_OPTS_RAW: MatchOpts = MatchOpts {
 };
 
 pub fn sld(host: &str) -> Option<Cow<'_, str>> {
-    PS_LIST::get().sld(host, ETLD_OPTS_RAW)
+    // sld c RAW_NORMALIZER не изменит контент, поэтому берем срез от host, а не держим ссылку на текущую версию PS_LIST
+    let len = PS_LIST::get().list.sld(host, ETLD_OPTS_RAW)?.len();
+    Some(Cow::Borrowed(&host[host.len() - len..]))
+}
+
+/// Host and its parent domains down to eTLD+1 (only the host itself if eTLD+1 is unknown)
+pub fn host_and_parents(host: &str) -> impl Iterator<Item = &str> {
+    let min_len = sld(host).map_or(host.len(), |s| s.len());
+    std::iter::successors(Some(host), move |h| {
+        if h.len() <= min_len {
+            None
+        } else {
+            h.split_once('.').map(|(_, parent)| parent)
+        }
+    })
 }
 
 pub struct AdblockFilter<'a> {
@@ -42,36 +68,36 @@ pub fn prepare_adblock_filter<'a>(mut content: &'a str) -> Option<AdblockFilter<
     content = match content.as_bytes()[0] {
         // h -> https://, http://
         b'h' => {
-            if content.starts_with("https://") {
-                &content[8..]
-            } else if content.starts_with("http://") {
-                &content[7..]
+            if let Some(rest) = content.strip_prefix("https://") {
+                rest
+            } else if let Some(rest) = content.strip_prefix("http://") {
+                rest
             } else {
                 content
             }
         }
         // w -> wss://, ws://
         b'w' => {
-            if content.starts_with("wss://") {
-                &content[6..]
-            } else if content.starts_with("ws://") {
-                &content[5..]
+            if let Some(rest) = content.strip_prefix("wss://") {
+                rest
+            } else if let Some(rest) = content.strip_prefix("ws://") {
+                rest
             } else {
                 content
             }
         }
         // / -> //
         b'/' => {
-            if content.starts_with("//") {
-                &content[2..]
+            if let Some(rest) = content.strip_prefix("//") {
+                rest
             } else {
                 content
             }
         }
         // : -> :// (правила без протокола)
         b':' => {
-            if content.starts_with("://") {
-                &content[3..]
+            if let Some(rest) = content.strip_prefix("://") {
+                rest
             } else {
                 content
             }
@@ -88,33 +114,35 @@ pub fn prepare_adblock_filter<'a>(mut content: &'a str) -> Option<AdblockFilter<
         domain = &domain[at_pos + 1..];
     }
 
-    if !domain.contains(':') && domain.contains('.') && domain.parse::<IpAddr>().is_err() {
-        if let Some(etld_plus1_raw) = PS_LIST::get().sld(domain, ETLD_OPTS_RAW) {
-            let d_len = domain.len();
-            let e1_len = etld_plus1_raw.len();
-            let etld_plus1 = &domain[d_len - e1_len..]; // sld c RAW_NORMALIZER не изменит контент, поэтому вместо Cow лучше взять срез от исходных данных, так избежим проблем с борроу чекером
+    if !domain.contains(':')
+        && domain.contains('.')
+        && domain.parse::<IpAddr>().is_err()
+        && let Some(etld_plus1_raw) = PS_LIST::get().list.sld(domain, ETLD_OPTS_RAW)
+    {
+        let d_len = domain.len();
+        let e1_len = etld_plus1_raw.len();
+        let etld_plus1 = &domain[d_len - e1_len..]; // sld c RAW_NORMALIZER не изменит контент, поэтому вместо Cow лучше взять срез от исходных данных, так избежим проблем с борроу чекером
 
-            let sub = domain[0..d_len - e1_len].trim_end_matches('.');
+        let sub = domain[0..d_len - e1_len].trim_end_matches('.');
 
-            let (sub_without_www, etld_plus_2_without_www) = if sub.is_empty() {
-                (sub, None)
-            } else if let Some(stripped) = sub.strip_suffix("www") {
-                (stripped.trim_end_matches('.'), Some(etld_plus1))
-            } else {
-                let e2_start = match domain[..sub.len()].rfind('.') {
-                    Some(pos) => pos + 1,
-                    None => 0,
-                };
-                (sub, Some(&domain[e2_start..]))
+        let (sub_without_www, etld_plus_2_without_www) = if sub.is_empty() {
+            (sub, None)
+        } else if let Some(stripped) = sub.strip_suffix("www") {
+            (stripped.trim_end_matches('.'), Some(etld_plus1))
+        } else {
+            let e2_start = match domain[..sub.len()].rfind('.') {
+                Some(pos) => pos + 1,
+                None => 0,
             };
+            (sub, Some(&domain[e2_start..]))
+        };
 
-            return Some(AdblockFilter {
-                domain,
-                sub_without_www,
-                etld_plus_2_without_www,
-                suffix,
-            });
-        }
+        return Some(AdblockFilter {
+            domain,
+            sub_without_www,
+            etld_plus_2_without_www,
+            suffix,
+        });
     }
 
     None
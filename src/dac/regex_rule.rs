@@ -0,0 +1,62 @@
+use regex::{Regex, RegexBuilder};
+use regex_syntax::hir::{Hir, HirKind};
+
+/// Adblock regexes are case-insensitive (`$match-case` rules are skipped)
+pub fn compile(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern).case_insensitive(true).build()
+}
+
+// самый длинный литерал, без которого regex не может совпасть
+fn longest_required(hir: &Hir) -> Option<&[u8]> {
+    match hir.kind() {
+        HirKind::Literal(lit) => Some(&lit.0),
+        HirKind::Capture(c) => longest_required(&c.sub),
+        HirKind::Repetition(r) if r.min > 0 => longest_required(&r.sub),
+        HirKind::Concat(subs) => subs
+            .iter()
+            .filter_map(longest_required)
+            .max_by_key(|l| l.len()),
+        // у альтернатив нет общего обязательного литерала
+        _ => None,
+    }
+}
+
+/// Literal used as the DAC key of the `/regex/` rule: the regex is run only when it is found in URL
+pub fn required_literal(pattern: &str) -> Option<String> {
+    let hir = regex_syntax::parse(pattern).ok()?;
+    let literal = str::from_utf8(longest_required(&hir)?).ok()?;
+    literal.is_ascii().then(|| literal.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_literal_is_the_longest_mandatory_part() {
+        assert_eq!(
+            required_literal(r"^https?://[a-z]+\.Tracker-Host\.com/p\d+").as_deref(),
+            Some(".tracker-host.com/p")
+        );
+        assert_eq!(
+            required_literal(r"(banner)+/\d{3}/x").as_deref(),
+            Some("banner")
+        );
+        // необязательные части и альтернативы не дают ключа
+        assert_eq!(required_literal(r"(?:adserver)?/x"), Some("/x".to_string()));
+        assert_eq!(required_literal(r"adserver|tracker"), None);
+        assert_eq!(required_literal(r"[a-z]+"), None);
+        assert_eq!(required_literal(r"(unclosed"), None);
+        assert_eq!(required_literal("реклама"), None);
+    }
+
+    #[test]
+    fn compiled_regex_ignores_case() {
+        assert!(
+            compile(r"/ADS/\d+")
+                .unwrap()
+                .is_match("https://x.com/ads/12")
+        );
+        assert!(compile(r"(").is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use hyper::{
+    HeaderMap,
+    header::{HeaderName, UPGRADE},
+};
+use std::fmt;
+
+/// Mask of resource types (`$script`, `$image`, ...) the rule applies to
+#[derive(PartialEq, Eq, Clone, Copy)]
+#[repr(transparent)]
+pub struct ResourceTypes(pub u16);
+
+const SEC_FETCH_DEST: HeaderName = HeaderName::from_static("sec-fetch-dest");
+
+impl ResourceTypes {
+    pub const SCRIPT: ResourceTypes = ResourceTypes(1 << 0);
+    pub const IMAGE: ResourceTypes = ResourceTypes(1 << 1);
+    pub const STYLESHEET: ResourceTypes = ResourceTypes(1 << 2);
+    pub const SUBDOCUMENT: ResourceTypes = ResourceTypes(1 << 3);
+    pub const XHR: ResourceTypes = ResourceTypes(1 << 4);
+    pub const MEDIA: ResourceTypes = ResourceTypes(1 << 5);
+    pub const FONT: ResourceTypes = ResourceTypes(1 << 6);
+    pub const PING: ResourceTypes = ResourceTypes(1 << 7);
+    pub const WEBSOCKET: ResourceTypes = ResourceTypes(1 << 8);
+    pub const OBJECT: ResourceTypes = ResourceTypes(1 << 9);
+    pub const OTHER: ResourceTypes = ResourceTypes(1 << 10);
+    pub const ALL: ResourceTypes = ResourceTypes((1 << 11) - 1);
+    pub const NONE: ResourceTypes = ResourceTypes(0);
+
+    /// Type option of the adblock rule (without `~`)
+    pub fn from_option(name: &str) -> Option<Self> {
+        Some(match name {
+            "script" => Self::SCRIPT,
+            "image" => Self::IMAGE,
+            "css" | "stylesheet" => Self::STYLESHEET,
+            "frame" | "subdocument" => Self::SUBDOCUMENT,
+            "xmlhttprequest" | "xhr" => Self::XHR,
+            "media" => Self::MEDIA,
+            "font" => Self::FONT,
+            "ping" | "beacon" => Self::PING,
+            "websocket" => Self::WEBSOCKET,
+            "object" | "object-subrequest" => Self::OBJECT,
+            "other" | "csp_report" | "webrtc" => Self::OTHER,
+            "all" => Self::ALL,
+            _ => return None,
+        })
+    }
+
+    /// Type of the request sent by the browser: `Sec-Fetch-Dest` or `Accept` if it's absent.
+    /// `None` for the top level documents, they are never blocked.
+    pub fn from_request(headers: &HeaderMap, accept: &str) -> Option<Self> {
+        if headers
+            .get(UPGRADE)
+            .is_some_and(|u| u.as_bytes().eq_ignore_ascii_case(b"websocket"))
+        {
+            return Some(Self::WEBSOCKET);
+        }
+        if let Some(dest) = headers.get(SEC_FETCH_DEST) {
+            return Some(match dest.as_bytes() {
+                b"document" => return None,
+                b"script" | b"worker" | b"sharedworker" | b"serviceworker" | b"audioworklet"
+                | b"paintworklet" => Self::SCRIPT,
+                b"image" => Self::IMAGE,
+                b"style" => Self::STYLESHEET,
+                b"iframe" | b"frame" | b"fencedframe" => Self::SUBDOCUMENT,
+                b"empty" => Self::XHR,
+                b"audio" | b"video" | b"track" => Self::MEDIA,
+                b"font" => Self::FONT,
+                b"report" => Self::PING,
+                b"object" | b"embed" => Self::OBJECT,
+                _ => Self::OTHER,
+            });
+        }
+        if accept.starts_with("text/html") || accept.starts_with("application/xhtml") {
+            return None;
+        }
+        Some(match &accept.as_bytes()[..accept.len().min(6)] {
+            b"image/" => Self::IMAGE,
+            b"video/" | b"audio/" => Self::MEDIA,
+            _ if accept.starts_with("text/css") => Self::STYLESHEET,
+            _ => Self::OTHER,
+        })
+    }
+
+    pub fn intersects(self, other: ResourceTypes) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+/// Names of the types as the rule options: `script,image`
+impl fmt::Display for ResourceTypes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if *self == Self::ALL {
+            return write!(f, "all");
+        }
+        let mut sep = "";
+        for (t, name) in [
+            (Self::SCRIPT, "script"),
+            (Self::IMAGE, "image"),
+            (Self::STYLESHEET, "stylesheet"),
+            (Self::SUBDOCUMENT, "subdocument"),
+            (Self::XHR, "xmlhttprequest"),
+            (Self::MEDIA, "media"),
+            (Self::FONT, "font"),
+            (Self::PING, "ping"),
+            (Self::WEBSOCKET, "websocket"),
+            (Self::OBJECT, "object"),
+            (Self::OTHER, "other"),
+        ] {
+            if self.intersects(t) {
+                write!(f, "{sep}{name}")?;
+                sep = ",";
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::ops::BitOr for ResourceTypes {
+    type Output = ResourceTypes;
+    fn bitor(self, rhs: ResourceTypes) -> ResourceTypes {
+        ResourceTypes(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitAnd for ResourceTypes {
+    type Output = ResourceTypes;
+    fn bitand(self, rhs: ResourceTypes) -> ResourceTypes {
+        ResourceTypes(self.0 & rhs.0)
+    }
+}
+
+impl std::ops::Not for ResourceTypes {
+    type Output = ResourceTypes;
+    fn not(self) -> ResourceTypes {
+        ResourceTypes(!self.0 & Self::ALL.0)
+    }
+}
@@ -0,0 +1,156 @@
+use crate::dac::pattern_type::PatternType;
+use std::fmt;
+
+/// Index of the longest literal fragment of the glob, it is used as the DAC key
+pub fn key_fragment(glob: &str) -> (usize, &str) {
+    glob.split('*').enumerate().fold(
+        (0, ""),
+        |best, (i, f)| if f.len() > best.1.len() { (i, f) } else { best },
+    )
+}
+
+/// Match ends at the end of URL or before query/fragment
+pub fn is_url_end(src: &str, end: usize) -> bool {
+    end == src.len() || matches!(src.as_bytes().get(end), Some(b'?' | b'#'))
+}
+
+// все вхождения, включая перекрывающиеся
+fn occurrences<'a>(hay: &'a str, needle: &'a str) -> impl Iterator<Item = usize> + 'a {
+    let mut from = 0;
+    std::iter::from_fn(move || {
+        let pos = from + hay.get(from..)?.find(needle)?;
+        from = pos + 1;
+        Some(pos)
+    })
+}
+
+/// Pattern with `*` wildcards, verified after the hit of its key fragment
+pub struct Wildcard {
+    fragments: Vec<String>,
+    key: usize,
+}
+
+impl Wildcard {
+    pub fn parse(glob: &str) -> Self {
+        Self {
+            fragments: glob.split('*').map(str::to_string).collect(),
+            key: key_fragment(glob).0,
+        }
+    }
+
+    /// `start..end` - position of the key fragment in `src`
+    pub fn is_match(&self, ptype: PatternType, src: &str, start: usize, end: usize) -> bool {
+        let (left, right) = (&self.fragments[..self.key], &self.fragments[self.key + 1..]);
+
+        // фрагменты слева ставим как можно правее, тогда у первого фрагмента больше всего вариантов позиции для проверки привязки
+        let is_left_match = match left.split_first() {
+            None => ptype.is_match_at(src, start),
+            Some((first, middle)) => {
+                let mut limit = start;
+                for f in middle.iter().rev() {
+                    match src[..limit].rfind(f.as_str()) {
+                        Some(pos) => limit = pos,
+                        None => return false,
+                    }
+                }
+                occurrences(&src[..limit], first).any(|pos| ptype.is_match_at(src, pos))
+            }
+        };
+        if !is_left_match {
+            return false;
+        }
+
+        // справа наоборот - как можно левее
+        match right.split_last() {
+            None => !ptype.is_end_anchored() || is_url_end(src, end),
+            Some((last, middle)) => {
+                let mut from = end;
+                for f in middle {
+                    match src[from..].find(f.as_str()) {
+                        Some(pos) => from += pos + f.len(),
+                        None => return false,
+                    }
+                }
+                occurrences(&src[from..], last)
+                    .any(|pos| !ptype.is_end_anchored() || is_url_end(src, from + pos + last.len()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Wildcard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.fragments.join("*"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // как DAC: проверка от каждого вхождения ключевого фрагмента
+    fn is_match(glob: &str, ptype: PatternType, src: &str) -> bool {
+        let w = Wildcard::parse(glob);
+        let key = key_fragment(glob).1;
+        occurrences(src, key).any(|start| w.is_match(ptype, src, start, start + key.len()))
+    }
+
+    #[test]
+    fn key_is_the_longest_fragment() {
+        assert_eq!(key_fragment("/ads*/banner-*.js"), (1, "/banner-"));
+        assert_eq!(key_fragment("*tracker*"), (1, "tracker"));
+        assert_eq!(key_fragment("plain"), (0, "plain"));
+    }
+
+    #[test]
+    fn fragments_match_around_the_key() {
+        let any = PatternType::Substring;
+        assert!(is_match(
+            "/ads*/banner-*.js",
+            any,
+            "https://x.com/ads/v1/banner-big.js"
+        ));
+        assert!(!is_match(
+            "/ads*/banner-*.js",
+            any,
+            "https://x.com/banner-big.js/ads"
+        ));
+        assert!(is_match("a*b*c*dddd", any, "https://x.com/a/c/b/c/dddd"));
+        assert!(!is_match("a*c*b*dddd", any, "https://x.com/c/a/b/dddd"));
+        assert_eq!(
+            Wildcard::parse("/ads*/banner-*.js").to_string(),
+            "/ads*/banner-*.js"
+        );
+    }
+
+    #[test]
+    fn start_and_end_anchors() {
+        let slashed = PatternType::SlashedStart;
+        assert!(is_match(
+            "//ads.*/pixel",
+            slashed,
+            "https://ads.x.com/pixel"
+        ));
+        assert!(!is_match(
+            "//ads.*/pixel",
+            slashed,
+            "https://x.com/?u=//ads.y/pixel"
+        ));
+
+        let end = PatternType::Substring.with_end_anchored(true);
+        assert!(is_match("/track*.gif", end, "https://x.com/track/1.gif"));
+        assert!(is_match(
+            "/track*.gif",
+            end,
+            "https://x.com/track/1.gif?v=2"
+        ));
+        // первое вхождение не у конца, подходит следующее
+        assert!(is_match(
+            "/track*.gif",
+            end,
+            "https://x.com/track/1.gif.png/2.gif"
+        ));
+        assert!(!is_match("/track*.gif", end, "https://x.com/track/1.gifx"));
+        assert!(is_url_end("a#b", 1) && is_url_end("ab", 2) && !is_url_end("ab", 1));
+    }
+}
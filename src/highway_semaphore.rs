@@ -67,6 +67,16 @@ impl HighwaySemaphore {
         }
     }
 
+    /// Free permits and number of waiters in the high and low priority queues
+    pub fn queue_depth(&self) -> (usize, usize, usize) {
+        let state = self.state.lock();
+        (
+            state.available,
+            state.high_queue.len(),
+            state.low_queue.len(),
+        )
+    }
+
     fn release_and_notify(&self) {
         let mut state = self.state.lock();
         state.available += 1;
@@ -15,6 +15,21 @@ macro_rules! initable_static {
         pub static $name: std::sync::LazyLock<$ty> = std::sync::LazyLock::new(|| $body);
     };
 
+    // --- Ветка 3: Reloadable (name = reloadable |args| -> Result { body }) ---
+    (
+        $name:ident = reloadable |$($arg:ident : $arg_ty:ty),*| -> Result<$ret_ty:ty, $err_ty:ty> $body:block ;
+        $($rest:tt)*
+    ) => {
+        initable_static! { @render_reloadable $name, |$($arg : $arg_ty),*| -> Result<$ret_ty, $err_ty> $body }
+        initable_static! { $($rest)* }
+    };
+    // Финальный элемент Reloadable
+    (
+        $name:ident = reloadable |$($arg:ident : $arg_ty:ty),*| -> Result<$ret_ty:ty, $err_ty:ty> $body:block $(;)?
+    ) => {
+        initable_static! { @render_reloadable $name, |$($arg : $arg_ty),*| -> Result<$ret_ty, $err_ty> $body }
+    };
+
     // --- Ветка 2: Initable (name = |args| -> Result { body }) ---
     (
         $name:ident = |$($arg:ident : $arg_ty:ty),*| -> Result<$ret_ty:ty, $err_ty:ty> $body:block ;
@@ -60,6 +75,40 @@ macro_rules! initable_static {
         }
     };
 
+    // --- Вспомогательный рендер логики ArcSwapOption ---
+    // init можно вызывать повторно: значение атомарно подменяется, а те, кто уже взял Arc на старое значение, дорабатывают с ним.
+    // build + store - то же самое в два шага, чтобы подменить несколько значений только если все они собрались
+    (@render_reloadable $name:ident, |$($arg:ident : $arg_ty:ty),*| -> Result<$ret_ty:ty, $err_ty:ty> $body:block) => {
+        pub static $name: ::arc_swap::ArcSwapOption<$ret_ty> = ::arc_swap::ArcSwapOption::const_empty();
+
+        #[allow(non_snake_case)]
+        pub mod $name {
+            use super::*;
+            #[allow(dead_code)]
+            pub fn init($($arg : $arg_ty),*) -> Result<::std::sync::Arc<$ret_ty>, $err_ty> {
+                let result = build($($arg),*)?;
+                store(result.clone());
+                Ok(result)
+            }
+
+            #[allow(dead_code)]
+            pub fn build($($arg : $arg_ty),*) -> Result<::std::sync::Arc<$ret_ty>, $err_ty> {
+                Ok(::std::sync::Arc::new((|$($arg : $arg_ty),*| -> Result<$ret_ty, $err_ty> { $body })($($arg),*)?))
+            }
+
+            #[allow(dead_code)]
+            pub fn store(value: ::std::sync::Arc<$ret_ty>) {
+                super::$name.store(Some(value));
+            }
+
+            #[inline]
+            #[allow(dead_code)]
+            pub fn get() -> ::std::sync::Arc<$ret_ty> {
+                super::$name.load_full().expect(concat!(stringify!($name), " is not initialized"))
+            }
+        }
+    };
+
     // Терминатор
     () => {};
 }
@@ -1,5 +1,7 @@
 use crate::{
-    cli::{APP_NAME, CLI},
+    block_log::BLOCK_LOG_FILE,
+    cli::{APP_NAME, CLI, Commands},
+    config::CONFIG,
     dac::psl::PS_LIST,
     maybe::UnifiedError,
 };
@@ -7,17 +9,22 @@ use enable_ansi_support::enable_ansi_support;
 use tracing::{self, level_filters::LevelFilter};
 use tracing_subscriber::EnvFilter;
 
+mod block_log;
 mod cancelation_token;
 mod cli;
+mod config;
 mod dac;
 mod highway_semaphore;
 mod initable_staticts;
 mod maybe;
+mod policy;
 mod processors;
 mod proxy;
+mod reload;
 mod resettable_lazy;
 
 fn main() -> Result<(), UnifiedError> {
+    CONFIG::init(config::config_path())?;
     PS_LIST::init(&CLI.psl)?;
 
     // fix colors in tracing
@@ -25,7 +32,7 @@ fn main() -> Result<(), UnifiedError> {
     tracing_subscriber::fmt()
         .without_time()
         .with_target(false)
-        .with_writer(|| std::io::stderr())
+        .with_writer(std::io::stderr)
         .with_env_filter(
             EnvFilter::builder()
                 .with_default_directive(LevelFilter::INFO.into())
@@ -33,13 +40,24 @@ fn main() -> Result<(), UnifiedError> {
         )
         .init();
 
-    if let Some(c) = &CLI.command {
-        dac::generate::run(c)
-    } else {
-        tokio::runtime::Builder::new_multi_thread()
-            .thread_name(const_str::concat!(APP_NAME, "-proxy"))
-            .enable_all()
-            .build()?
-            .block_on(proxy::run())
+    match &CLI.command {
+        Some(Commands::Config { action }) => config::run(action),
+        Some(Commands::Stats { log, top }) => block_log::stats(
+            log.as_deref()
+                .or(CLI.block_log.as_deref())
+                .ok_or("Pass the block log path or set --block-log")?,
+            *top,
+        ),
+        Some(c) => dac::generate::run(c),
+        None => {
+            if let Some(path) = &CLI.block_log {
+                BLOCK_LOG_FILE::init(path)?;
+            }
+            tokio::runtime::Builder::new_multi_thread()
+                .thread_name(const_str::concat!(APP_NAME, "-proxy"))
+                .enable_all()
+                .build()?
+                .block_on(proxy::run())
+        }
     }
 }
@@ -0,0 +1,296 @@
+use crate::{cli::Cli, config::FileConfig, dac::psl, initable_static, maybe::UnifiedError};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+
+/// `[[rules]]` entry of the config file
+#[derive(Deserialize, Serialize, Default, Clone)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct HostRule {
+    /// Hosts or eTLD+1 (subdomains are included), `*` for any host
+    pub hosts: Vec<String>,
+    /// Path globs (`*` - any sequence, `?` - any char), empty list matches any path
+    pub paths: Vec<String>,
+    /// Proxy users (see `--proxy-users`), empty list matches anyone
+    pub users: Vec<String>,
+    /// Tunnel the host without MITM and any transformation
+    pub bypass: bool,
+    /// Connect to the host without `--upstream-proxy`
+    pub direct: bool,
+    /// Don't verify the upstream certificate and host name
+    pub insecure: bool,
+    /// SHA-256 of the upstream certificate public key, `sha256/BASE64`
+    pub pins: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html_clean: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_scale: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fast_304: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_aux_resources: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub html_rechunk_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_svc: Option<AltSvc>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "de_duration"
+    )]
+    pub connect_timeout: Option<u32>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "de_duration"
+    )]
+    pub tls_timeout: Option<u32>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "de_duration"
+    )]
+    pub headers_timeout: Option<u32>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        deserialize_with = "de_duration"
+    )]
+    pub body_idle_timeout: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+}
+
+// секунды числом или строка как у флагов: "10s", "2m"
+fn de_duration<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        Secs(u32),
+        Human(String),
+    }
+    Ok(match Option::<Value>::deserialize(d)? {
+        None => None,
+        Some(Value::Secs(secs)) => Some(secs),
+        Some(Value::Human(s)) => Some(
+            s.parse::<human_units::Duration>()
+                .map_err(serde::de::Error::custom)?
+                .as_secs() as u32,
+        ),
+    })
+}
+
+/// What to do with `Alt-Svc` response headers
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AltSvc {
+    /// Pass as is, browsers may switch to QUIC and bypass the proxy
+    Keep,
+    /// Remove, browsers stay on TCP through the proxy
+    Strip,
+    /// Keep only `h3` on the same host, for QUIC redirected to `--h3-listen` (strip without it)
+    Rewrite,
+}
+
+impl AltSvc {
+    pub fn parse(s: &str) -> Result<Self, UnifiedError> {
+        match s.trim_ascii() {
+            "keep" => Ok(Self::Keep),
+            "strip" => Ok(Self::Strip),
+            "rewrite" => Ok(Self::Rewrite),
+            other => {
+                Err(format!("Invalid Alt-Svc mode '{other}', use keep, strip or rewrite").into())
+            }
+        }
+    }
+}
+
+impl HostRule {
+    fn is_path_match(&self, path: &str) -> bool {
+        self.paths.is_empty()
+            || self
+                .paths
+                .iter()
+                .any(|p| glob_match(p.as_bytes(), path.as_bytes()))
+    }
+
+    fn is_user_match(&self, user: Option<&str>) -> bool {
+        self.users.is_empty() || user.is_some_and(|u| self.users.iter().any(|x| x == u))
+    }
+}
+
+/// Effective per request settings
+#[derive(Clone)]
+pub struct Policy {
+    pub bypass: bool,
+    pub direct: bool,
+    pub insecure: bool,
+    pub pins: Vec<String>,
+    pub html_clean: bool,
+    pub image_scale: f32,
+    pub fast_304: bool,
+    pub skip_aux_resources: bool,
+    pub html_rechunk_size: usize,
+    pub alt_svc: AltSvc,
+    /// Upstream phase timeouts, seconds
+    pub connect_timeout: u32,
+    pub tls_timeout: u32,
+    pub headers_timeout: u32,
+    pub body_idle_timeout: u32,
+    pub retries: u32,
+}
+
+pub struct PolicyRules {
+    base: Policy,
+    rules: Vec<HostRule>,
+    // host (lowercase) -> indexes of rules in file order
+    index: HashMap<String, Vec<usize>>,
+}
+
+initable_static! {
+    POLICY_RULES = reloadable |cli: &Cli, config: &FileConfig| -> Result<PolicyRules, UnifiedError> {
+        let mut index: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, rule) in config.rules.iter().enumerate() {
+            for host in &rule.hosts {
+                let host = host.trim_ascii().trim_start_matches("*.").trim_matches('.');
+                if !host.is_empty() {
+                    index.entry(host.to_ascii_lowercase()).or_default().push(i);
+                }
+            }
+        }
+        Ok(PolicyRules {
+            base: Policy {
+                bypass: false,
+                direct: false,
+                insecure: false,
+                pins: Vec::new(),
+                html_clean: cli.html_clean,
+                image_scale: cli.image_scale,
+                fast_304: cli.fast_304,
+                skip_aux_resources: cli.skip_aux_resources,
+                html_rechunk_size: cli.html_rechunk_size,
+                alt_svc: cli.alt_svc,
+                connect_timeout: cli.connect_timeout,
+                tls_timeout: cli.tls_timeout,
+                headers_timeout: cli.headers_timeout,
+                body_idle_timeout: cli.body_idle_timeout,
+                retries: cli.retries,
+            },
+            rules: config.rules.clone(),
+            index,
+        })
+    };
+}
+
+impl PolicyRules {
+    fn matched(&self, host: &str, user: Option<&str>) -> impl Iterator<Item = &HostRule> {
+        let mut found = Vec::new();
+        if !self.index.is_empty() {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+
+            // host, parent domains up to eTLD+1 and '*'
+            for candidate in psl::host_and_parents(&host) {
+                if let Some(rules) = self.index.get(candidate) {
+                    found.extend_from_slice(rules);
+                }
+            }
+            if let Some(rules) = self.index.get("*") {
+                found.extend_from_slice(rules);
+            }
+
+            found.sort_unstable();
+            found.dedup();
+        }
+        found
+            .into_iter()
+            .map(|i| &self.rules[i])
+            .filter(move |r| r.is_user_match(user))
+    }
+}
+
+impl Policy {
+    /// Global settings overridden by all matched rules (later rules win)
+    pub fn for_request(host: Option<&str>, path: &str, user: Option<&str>) -> Self {
+        let rules = POLICY_RULES::get();
+        let mut policy = rules.base.clone();
+        let Some(host) = host else {
+            return policy;
+        };
+        for rule in rules.matched(host, user).filter(|r| r.is_path_match(path)) {
+            policy.bypass |= rule.bypass;
+            policy.direct |= rule.direct;
+            policy.insecure |= rule.insecure;
+            if !rule.pins.is_empty() {
+                policy.pins.clone_from(&rule.pins);
+            }
+            macro_rules! apply {
+                ($($field:ident),*) => {
+                    $(if let Some(v) = rule.$field { policy.$field = v; })*
+                };
+            }
+            apply!(
+                html_clean,
+                image_scale,
+                fast_304,
+                skip_aux_resources,
+                html_rechunk_size,
+                alt_svc,
+                connect_timeout,
+                tls_timeout,
+                headers_timeout,
+                body_idle_timeout,
+                retries
+            );
+        }
+        policy
+    }
+
+    /// Used for CONNECT requests, where the path is unknown, so only rules without path globs are considered
+    pub fn is_bypassed_host(host: &str, user: Option<&str>) -> bool {
+        POLICY_RULES::get()
+            .matched(host, user)
+            .any(|r| r.bypass && r.paths.is_empty())
+    }
+
+    /// Same as `is_bypassed_host` for the `direct` rules
+    pub fn is_direct_host(host: &str, user: Option<&str>) -> bool {
+        POLICY_RULES::get()
+            .matched(host, user)
+            .any(|r| r.direct && r.paths.is_empty())
+    }
+}
+
+fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while i < s.len() {
+        if p < pattern.len() && (pattern[p] == b'?' || pattern[p] == s[i]) {
+            p += 1;
+            i += 1;
+        } else if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, i));
+            p += 1;
+        } else if let Some((bp, bi)) = backtrack {
+            p = bp + 1;
+            i = bi + 1;
+            backtrack = Some((bp, bi + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn misspelled_rule_key_is_an_error() {
+        let config: FileConfig =
+            toml::from_str("[[rules]]\nhosts = [\"example.com\"]\nhtml-clean = false\n").unwrap();
+        assert_eq!(config.rules[0].html_clean, Some(false));
+        let err = toml::from_str::<FileConfig>(
+            "[[rules]]\nhosts = [\"example.com\"]\nhtml-clen = false\n",
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("html-clen"), "{err}");
+    }
+}
@@ -75,10 +75,10 @@ impl CompressionAlgo {
                 _ => None,
             };
 
-            if let Some((algo, overhead)) = result {
-                if compressed.len() + overhead < original_len {
-                    return (algo, Bytes::from(compressed));
-                }
+            if let Some((algo, overhead)) = result
+                && compressed.len() + overhead < original_len
+            {
+                return (algo, Bytes::from(compressed));
             }
         }
 
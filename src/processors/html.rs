@@ -1,13 +1,17 @@
 use crate::{
-    cli::CLI,
-    dac::{self, UrlBaseInfo, psl},
+    config::CONFIG,
+    dac::{DAC, UrlBaseInfo, psl, resource_type::ResourceTypes},
     initable_static, maybe,
+    policy::Policy,
     resettable_lazy::ResettableLazy,
 };
 use fastvec::FastVec;
-use lol_html::{RewriteStrSettings, comments, element, rewrite_str};
+use lol_html::{
+    ElementContentHandlers, HandlerResult, RewriteStrSettings, comments, element,
+    html_content::Element, rewrite_str,
+};
 use memchr::memmem::Finder;
-use std::cell::RefCell;
+use std::{borrow::Cow, cell::RefCell};
 use url::Url;
 
 initable_static! {
@@ -15,13 +19,14 @@ initable_static! {
     FINDER_UPPER: Finder<'static> = || { Finder::new(b"</SCRIPT") };
 }
 
-pub fn minify<'a>(html: String, async_load_styles: bool, uri: &'a str) -> String {
+pub fn minify(html: String, async_load_styles: bool, uri: &str, policy: &Policy) -> String {
     let base_info: RefCell<Option<String>> = None.into();
     let etld_1_info = ResettableLazy::new(|| -> Option<UrlBaseInfo> {
-        let url = Url::parse(&uri).ok()?;
+        let url = Url::parse(uri).ok()?;
         let host = url.host_str()?;
 
         Some(UrlBaseInfo {
+            host: host.to_ascii_lowercase(),
             etld_plus1: psl::sld(host)?.to_string(),
             base: url
                 .join(base_info.borrow().as_deref().unwrap_or("./"))
@@ -53,9 +58,19 @@ pub fn minify<'a>(html: String, async_load_styles: bool, uri: &'a str) -> String
             }
         };
     }
-    let can_scale_image = CLI.image_scale > 0.0;
+    let can_scale_image = policy.image_scale > 0.0;
+    // версии на момент начала запроса, даже если во время обработки произойдет reload
+    let dac = DAC.load_full();
+    let config = CONFIG::get();
+    let blocked_params = &config.blocked_params;
+    let page_host = Url::parse(uri)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_string));
+    let cosmetic = dac
+        .as_ref()
+        .map(|d| d.cosmetic.for_host(page_host.as_deref()));
     let source_bytes: &[u8] = html.as_ref();
-    let settings = RewriteStrSettings {
+    let mut settings = RewriteStrSettings {
         element_content_handlers: vec![
             element!("base", |el| {
                 if base_info.borrow().is_none() {
@@ -128,17 +143,16 @@ pub fn minify<'a>(html: String, async_load_styles: bool, uri: &'a str) -> String
                 Ok(())
             }),
             element!("script", |el| {
-                if let Some(src) = el.attributes().iter().find_map(|attr| {
-                    if attr.name_raw() == b"src" {
-                        Some(attr.value_raw())
-                    } else {
-                        None
-                    }
-                }) {
-                    if dac::is_match_src(src, &etld_1_info) {
+                let src_blocked = src_attribute(el).map(|src| {
+                    dac.as_ref().is_some_and(|d| {
+                        d.is_match_src(&src, &etld_1_info, ResourceTypes::SCRIPT, 0)
+                    })
+                });
+                if let Some(blocked) = src_blocked {
+                    if blocked {
                         el.remove();
                     }
-                } else {
+                } else if let Some(dac) = &dac {
                     let tag_location = el.source_location().bytes();
                     let content_started_at = tag_location.end;
 
@@ -149,15 +163,30 @@ pub fn minify<'a>(html: String, async_load_styles: bool, uri: &'a str) -> String
                             .find(search_area)
                             .or_else(|| FINDER_UPPER.find(search_area));
 
-                        if let Some(offset) = found_offset {
-                            if dac::is_match_code(&search_area[..offset], &etld_1_info) {
-                                el.remove();
-                            }
+                        if let Some(offset) = found_offset
+                            && dac.is_match_code(&search_area[..offset], &etld_1_info)
+                        {
+                            el.remove();
                         }
                     }
                 }
                 Ok(())
             }),
+            element!("img[src], iframe[src]", |el| {
+                if let Some(dac) = &dac {
+                    let resource_type = if el.tag_name() == "iframe" {
+                        ResourceTypes::SUBDOCUMENT
+                    } else {
+                        ResourceTypes::IMAGE
+                    };
+                    if src_attribute(el)
+                        .is_some_and(|src| dac.is_match_src(&src, &etld_1_info, resource_type, 0))
+                    {
+                        el.remove();
+                    }
+                }
+                Ok(())
+            }),
             element!("table[summary]", |el| {
                 el.remove_attribute("summary");
                 Ok(())
@@ -254,7 +283,12 @@ pub fn minify<'a>(html: String, async_load_styles: bool, uri: &'a str) -> String
                                             b"rb_" => !key.starts_with(b"rb_clickid"),
                                             _ => true,
                                         }
-                                    };
+                                    } && !blocked_params.iter().any(|p| {
+                                        match p.strip_suffix('*') {
+                                            Some(prefix) => key.starts_with(prefix.as_bytes()),
+                                            None => key == p.as_bytes(),
+                                        }
+                                    });
 
                                     if keep {
                                         if !first {
@@ -337,6 +371,19 @@ pub fn minify<'a>(html: String, async_load_styles: bool, uri: &'a str) -> String
                 }
             ),
             element!("*", |el| {
+                if let Some(cosmetic) = &cosmetic {
+                    let (mut id, mut class) = (None, None);
+                    for attr in el.attributes().iter() {
+                        match attr.name_raw() {
+                            b"id" => id = Some(attr.value_raw()),
+                            b"class" => class = Some(attr.value_raw()),
+                            _ => {}
+                        }
+                    }
+                    if cosmetic.is_generic_hidden(id, class) {
+                        el.remove();
+                    }
+                }
                 if !el.removed() {
                     let to_remove: FastVec<String, 4> = el
                         .attributes()
@@ -367,5 +414,86 @@ pub fn minify<'a>(html: String, async_load_styles: bool, uri: &'a str) -> String
         ],
         ..Default::default()
     };
+    if let Some(cosmetic) = &cosmetic {
+        settings
+            .element_content_handlers
+            .extend(cosmetic.selectors.iter().map(|selector| {
+                (
+                    Cow::Borrowed(selector.as_ref()),
+                    ElementContentHandlers::default().element(
+                        |el: &mut Element<'_, '_>| -> HandlerResult {
+                            el.remove();
+                            Ok(())
+                        },
+                    ),
+                )
+            }));
+    }
     rewrite_str(&html, settings).unwrap_or(html)
 }
+
+/// `src` as the browser requests it: `?a=1&amp;b=2` is `?a=1&b=2`
+fn src_attribute<'a>(el: &'a Element) -> Option<Cow<'a, [u8]>> {
+    el.attributes()
+        .iter()
+        .find(|attr| attr.name_raw() == b"src")
+        .map(|attr| decode_char_refs(attr.value_raw()))
+}
+
+/// Decodes numeric and the XML named character references, others are kept as is
+fn decode_char_refs(value: &[u8]) -> Cow<'_, [u8]> {
+    if memchr::memchr(b'&', value).is_none() {
+        return Cow::Borrowed(value);
+    }
+    let mut out = Vec::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(pos) = memchr::memchr(b'&', rest) {
+        out.extend_from_slice(&rest[..pos]);
+        rest = &rest[pos..];
+        let decoded = memchr::memchr(b';', rest).and_then(|end| {
+            let c = match &rest[1..end] {
+                b"amp" => '&',
+                b"lt" => '<',
+                b"gt" => '>',
+                b"quot" => '"',
+                b"apos" => '\'',
+                [b'#', b'x' | b'X', hex @ ..] => {
+                    char::from_u32(u32::from_str_radix(str::from_utf8(hex).ok()?, 16).ok()?)?
+                }
+                [b'#', dec @ ..] => char::from_u32(str::from_utf8(dec).ok()?.parse().ok()?)?,
+                _ => return None,
+            };
+            Some((c, end + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                rest = &rest[len..];
+            }
+            None => {
+                out.push(b'&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.extend_from_slice(rest);
+    Cow::Owned(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn char_refs_in_src() {
+        assert_eq!(&*decode_char_refs(b"/ad.js?a=1&amp;b=2"), b"/ad.js?a=1&b=2");
+        assert_eq!(&*decode_char_refs(b"/ad.js?a=1&#38;b=2&#x26;c"), b"/ad.js?a=1&b=2&c");
+        assert!(matches!(decode_char_refs(b"/ad.js?a=1"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn unknown_char_refs_are_kept() {
+        assert_eq!(&*decode_char_refs(b"?a&b=1;c"), b"?a&b=1;c");
+        assert_eq!(&*decode_char_refs(b"?x=&copy;&#xZZ;&"), b"?x=&copy;&#xZZ;&");
+    }
+}
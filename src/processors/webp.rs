@@ -5,7 +5,7 @@ use image::{GrayAlphaImage, GrayImage, imageops::FilterType};
 use libwebp_sys::*;
 use std::{ptr, sync::OnceLock};
 
-pub fn thumbnail(data: Vec<u8>) -> Result<Vec<u8>, UnifiedError> {
+pub fn thumbnail(data: Vec<u8>, scale: f32) -> Result<Vec<u8>, UnifiedError> {
     let mut img = image::load_from_memory(&data)?;
     drop(data);
 
@@ -16,7 +16,7 @@ pub fn thumbnail(data: Vec<u8>) -> Result<Vec<u8>, UnifiedError> {
 
     let min: f32 = cli.image_scale_limit[0] as f32;
     let max: f32 = cli.image_scale_limit[1] as f32;
-    let mut ratio: f32 = cli.image_scale;
+    let mut ratio: f32 = scale;
 
     if min_orig * ratio < min {
         ratio = min / min_orig;
@@ -181,19 +181,17 @@ unsafe fn execute_encode(picture: &mut WebPPicture) -> Result<Vec<u8>, String> {
     picture.writer = Some(WebPMemoryWrite);
     picture.custom_ptr = &mut writer_mem as *mut _ as *mut std::ffi::c_void;
 
-    let result = if WebPEncode(config, picture) != 0 {
+    if WebPEncode(config, picture) != 0 {
         WebPPictureFree(picture);
         Ok(Vec::from_raw_parts(
             writer_mem.mem,
-            writer_mem.size as usize,
-            writer_mem.max_size as usize,
+            writer_mem.size,
+            writer_mem.max_size,
         ))
     } else {
         let code = picture.error_code;
         WebPPictureFree(picture);
         WebPMemoryWriterClear(&mut writer_mem);
         Err(format!("WebPEncode failed with error: {:?}", code))
-    };
-
-    result
+    }
 }
@@ -0,0 +1,292 @@
+use crate::{
+    block_log,
+    cli::{APP_NAME, Cli},
+    config::FileConfig,
+    initable_static,
+    maybe::UnifiedError,
+};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use hyper::{
+    HeaderMap, Method, Uri,
+    header::{HeaderValue, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION},
+};
+use md5::{Digest, Md5};
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    hash::{BuildHasher, RandomState},
+    sync::{Arc, LazyLock},
+};
+
+const NONCE_TTL_SECS: u64 = 300;
+
+initable_static! {
+    PROXY_USERS = reloadable |cli: &Cli, config: &FileConfig| -> Result<HashMap<String, String>, UnifiedError> {
+        let mut users: HashMap<String, String> = config.users.clone().into_iter().collect();
+        if let Some(list) = &cli.proxy_users {
+            for entry in list.split(',').map(str::trim_ascii).filter(|e| !e.is_empty()) {
+                let (name, password) = entry
+                    .split_once(':')
+                    .ok_or_else(|| format!("Invalid proxy user '{entry}', use 'USER:PASS'"))?;
+                users.insert(name.to_string(), password.to_string());
+            }
+        }
+        Ok(users)
+    };
+}
+
+// ключ подписи nonce, новый при каждом запуске
+static NONCE_KEY: LazyLock<RandomState> = LazyLock::new(RandomState::new);
+
+// принятые nc для каждого nonce, пока он не истёк
+static SEEN_NC: Mutex<BTreeMap<String, BTreeSet<u32>>> = Mutex::new(BTreeMap::new());
+
+/// Authenticated proxy user, stored in the request extensions
+#[derive(Clone)]
+pub struct ProxyUser(Arc<str>);
+
+impl ProxyUser {
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+/// `407 Proxy Authentication Required` challenge
+pub struct Challenge {
+    stale: bool,
+}
+
+impl Challenge {
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let digest = format!(
+            "Digest realm=\"{APP_NAME}\", qop=\"auth\", algorithm=MD5, nonce=\"{}\"{}",
+            new_nonce(),
+            if self.stale { ", stale=true" } else { "" }
+        );
+        if let Ok(v) = HeaderValue::from_str(&digest) {
+            headers.append(PROXY_AUTHENTICATE, v);
+        }
+        headers.append(
+            PROXY_AUTHENTICATE,
+            HeaderValue::from_static(const_str::concat!(
+                "Basic realm=\"",
+                APP_NAME,
+                "\", charset=\"UTF-8\""
+            )),
+        );
+    }
+}
+
+/// `Ok(None)` if no users are configured
+pub fn authenticate(
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+) -> Result<Option<ProxyUser>, Challenge> {
+    let users = PROXY_USERS::get();
+    if users.is_empty() {
+        return Ok(None);
+    }
+
+    let mut stale = false;
+    for value in headers.get_all(PROXY_AUTHORIZATION) {
+        let Ok(value) = value.to_str() else { continue };
+        let (scheme, credentials) = value.trim_ascii().split_once(' ').unwrap_or((value, ""));
+        let user = if scheme.eq_ignore_ascii_case("basic") {
+            basic(&users, credentials)
+        } else if scheme.eq_ignore_ascii_case("digest") {
+            digest(&users, method, uri, credentials, &mut stale)
+        } else {
+            None
+        };
+        if let Some(name) = user {
+            return Ok(Some(ProxyUser(name.into())));
+        }
+    }
+    Err(Challenge { stale })
+}
+
+pub fn is_enabled() -> bool {
+    !PROXY_USERS::get().is_empty()
+}
+
+/// Plain name and password check (SOCKS5)
+pub fn check_password(name: &str, password: &str) -> Option<ProxyUser> {
+    password_match(&PROXY_USERS::get(), name, password).map(|name| ProxyUser(name.into()))
+}
+
+fn password_match<'a>(
+    users: &'a HashMap<String, String>,
+    name: &str,
+    password: &str,
+) -> Option<&'a str> {
+    let (name, expected) = users.get_key_value(name)?;
+    eq(password.as_bytes(), expected.as_bytes()).then_some(name.as_str())
+}
+
+fn basic<'a>(users: &'a HashMap<String, String>, credentials: &str) -> Option<&'a str> {
+    let decoded = STANDARD.decode(credentials.trim_ascii()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (name, password) = decoded.split_once(':')?;
+    password_match(users, name, password)
+}
+
+// RFC 7616 с MD5 и qop=auth; ответ привязан к адресу запроса, повтор nc с тем же nonce отвергается
+fn digest<'a>(
+    users: &'a HashMap<String, String>,
+    method: &Method,
+    uri: &Uri,
+    credentials: &str,
+    stale: &mut bool,
+) -> Option<&'a str> {
+    let params = digest_params(credentials);
+    let (name, password) = users.get_key_value(*params.get("username")?)?;
+    let nonce = *params.get("nonce")?;
+    if !nonce_is_fresh(nonce)? {
+        *stale = true;
+        return None;
+    }
+    let digest_uri = *params.get("uri")?;
+    if *params.get("qop")? != "auth" || !uri_matches(method, uri, digest_uri) {
+        return None;
+    }
+    let nc = *params.get("nc")?;
+
+    let ha1 = md5_hex(&format!("{name}:{APP_NAME}:{password}"));
+    let ha2 = md5_hex(&format!("{method}:{digest_uri}"));
+    let expected = md5_hex(&format!(
+        "{ha1}:{nonce}:{nc}:{}:auth:{ha2}",
+        params.get("cnonce")?
+    ));
+    if !eq(params.get("response")?.as_bytes(), expected.as_bytes()) {
+        return None;
+    }
+    // повтор перехваченного заголовка; честный клиент получит новый nonce без запроса пароля
+    if !first_use(nonce, u32::from_str_radix(nc, 16).ok()?) {
+        *stale = true;
+        return None;
+    }
+    Some(name.as_str())
+}
+
+/// `uri` directive is the request target: the authority for CONNECT, else the absolute or origin form
+fn uri_matches(method: &Method, uri: &Uri, digest_uri: &str) -> bool {
+    if method == Method::CONNECT {
+        return uri
+            .authority()
+            .is_some_and(|a| a.as_str().eq_ignore_ascii_case(digest_uri));
+    }
+    match digest_uri.parse::<Uri>() {
+        Ok(digest_uri) if digest_uri.scheme().is_some() => digest_uri == *uri,
+        Ok(digest_uri) => digest_uri.path_and_query() == uri.path_and_query(),
+        Err(_) => false,
+    }
+}
+
+/// `false` if this `nc` was already accepted with the nonce
+fn first_use(nonce: &str, nc: u32) -> bool {
+    let mut seen = SEEN_NC.lock();
+    if !seen.contains_key(nonce) {
+        seen.retain(|nonce, _| nonce_is_fresh(nonce) == Some(true));
+    }
+    seen.entry(nonce.to_string()).or_default().insert(nc)
+}
+
+fn digest_params(s: &str) -> HashMap<&str, &str> {
+    let mut params = HashMap::new();
+    let mut rest = s;
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        let Some((key, tail)) = rest.split_once('=') else {
+            break;
+        };
+        let (value, tail) = match tail.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some(v) => v,
+                None => break,
+            },
+            None => tail.split_once(',').unwrap_or((tail, "")),
+        };
+        params.insert(key.trim_ascii(), value.trim_ascii());
+        rest = tail;
+    }
+    params
+}
+
+fn md5_hex(s: &str) -> String {
+    format!("{:x}", Md5::digest(s.as_bytes()))
+}
+
+// сравнение без раннего выхода
+fn eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// nonce = время выдачи и его подпись, поэтому хранить выданные nonce не нужно
+fn new_nonce() -> String {
+    let time = block_log::now();
+    format!("{time:x}.{:x}", NONCE_KEY.hash_one(time))
+}
+
+/// `None` if the nonce is forged
+fn nonce_is_fresh(nonce: &str) -> Option<bool> {
+    let (time, sign) = nonce.split_once('.')?;
+    let time = u64::from_str_radix(time, 16).ok()?;
+    let sign = u64::from_str_radix(sign, 16).ok()?;
+    (NONCE_KEY.hash_one(time) == sign)
+        .then(|| block_log::now().saturating_sub(time) <= NONCE_TTL_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest_params_with_quoted_commas() {
+        let params = digest_params(
+            r#"username="bob", uri="/search?q=a,b", qop=auth,nc=00000001 , cnonce="x, y""#,
+        );
+        assert_eq!(params["username"], "bob");
+        assert_eq!(params["uri"], "/search?q=a,b");
+        assert_eq!(params["qop"], "auth");
+        assert_eq!(params["nc"], "00000001");
+        assert_eq!(params["cnonce"], "x, y");
+    }
+
+    #[test]
+    fn digest_params_with_unterminated_quote() {
+        let params = digest_params(r#"realm=zhlob, username="bob, nc=00000001"#);
+        assert_eq!(params["realm"], "zhlob");
+        assert!(!params.contains_key("username"));
+        assert!(!params.contains_key("nc"));
+    }
+
+    #[test]
+    fn digest_uri_is_the_request_target() {
+        let connect: Uri = "example.com:443".parse().unwrap();
+        assert!(uri_matches(&Method::CONNECT, &connect, "example.com:443"));
+        assert!(!uri_matches(&Method::CONNECT, &connect, "other.com:443"));
+
+        let absolute: Uri = "http://example.com/a?b=1".parse().unwrap();
+        assert!(uri_matches(
+            &Method::GET,
+            &absolute,
+            "http://example.com/a?b=1"
+        ));
+        assert!(uri_matches(&Method::GET, &absolute, "/a?b=1"));
+        assert!(!uri_matches(&Method::GET, &absolute, "/other"));
+        assert!(!uri_matches(
+            &Method::GET,
+            &absolute,
+            "http://other.com/a?b=1"
+        ));
+    }
+
+    #[test]
+    fn nonce_count_is_accepted_once() {
+        let nonce = new_nonce();
+        assert!(first_use(&nonce, 1));
+        assert!(!first_use(&nonce, 1));
+        assert!(first_use(&nonce, 2));
+    }
+}
@@ -62,7 +62,7 @@ pub impl Bytes {
             }
         }
 
-        return b.body(self.to_boxed_body()).unwrap();
+        b.body(self.to_boxed_body()).unwrap()
     }
 
     fn to_boxed_body(self) -> BoxBody<Bytes, hyper::Error> {
@@ -49,7 +49,7 @@ impl<'a> CertifiedKeyDer<'a> {
 
         let mut key = key_pair.serialize_der();
         debug_assert_eq!(key.len(), Self::KEY_SIZE);
-        key.extend_from_slice(&cert.der().to_vec());
+        key.extend_from_slice(cert.der());
         Ok(key)
     }
 }
@@ -67,14 +67,14 @@ initable_static! {
     CERT_PATHS: CertPaths = || {
         let cert_dir = BASE_DIRS.home_dir().join(const_str::concat!(".", APP_NAME));
 
-        fs::create_dir_all(&cert_dir).expect(&format!(
+        fs::create_dir_all(&cert_dir).unwrap_or_else(|_| panic!(
             "Failed to create certificate directory: {}",
             cert_dir.display()
         ));
         #[cfg(unix)]
         {
             use std::os::unix::fs::PermissionsExt;
-            fs::set_permissions(&cert_dir, fs::Permissions::from_mode(0o700)).expect(&format!(
+            fs::set_permissions(&cert_dir, fs::Permissions::from_mode(0o700)).unwrap_or_else(|_| panic!(
                 "Failed to set directory permissions for: {}",
                 cert_dir.display()
             ));
@@ -92,19 +92,18 @@ initable_static! {
 }
 
 fn write_file_with_permissions(path: &Path, content: &[u8], description: &str) {
-    fs::write(path, content).expect(&format!(
-        "Failed to write {} to {}",
-        description,
-        path.display()
-    ));
+    fs::write(path, content)
+        .unwrap_or_else(|_| panic!("Failed to write {} to {}", description, path.display()));
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
-        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).expect(&format!(
-            "Failed to set permissions for {}: {}",
-            description,
-            path.display()
-        ));
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap_or_else(|_| {
+            panic!(
+                "Failed to set permissions for {}: {}",
+                description,
+                path.display()
+            )
+        });
     }
 }
 
@@ -0,0 +1,820 @@
+use crate::{
+    cli::CLI,
+    maybe::UnifiedError,
+    policy::Policy,
+    proxy::{
+        pool::{Pool, PoolKey, RequestBody, SendError, Sender},
+        upstream::{self, UpstreamProxy, UpstreamScheme},
+    },
+};
+use base64::{Engine as _, engine::general_purpose::STANDARD};
+use http_body_util::{BodyExt, Empty};
+use hyper::{
+    Method, Request, Response, StatusCode, Uri,
+    body::{Body, Incoming},
+    client::conn::{http1, http2},
+    header::{HOST, HeaderValue, PROXY_AUTHORIZATION},
+    http::uri::Scheme,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use sha2::{Digest, Sha256};
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+    error::Error as StdError, fmt, future::Future, io, path::Path, sync::Arc, time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, lookup_host},
+    time::timeout,
+};
+use tokio_rustls::{
+    TlsConnector,
+    client::TlsStream,
+    rustls::{
+        self, CertificateError, ClientConfig, DigitallySignedStruct, OtherError, RootCertStore,
+        SignatureScheme,
+        client::{
+            WebPkiServerVerifier,
+            danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+            verify_server_cert_signed_by_trust_anchor,
+        },
+        crypto::{CryptoProvider, WebPkiSupportedAlgorithms},
+        pki_types::{CertificateDer, ServerName, TrustAnchor, UnixTime, pem::PemObject},
+        server::ParsedCertificate,
+    },
+};
+
+/// Sends requests directly or through `--upstream-proxy`, reusing keep-alive connections
+#[derive(Clone)]
+pub struct Client {
+    /// System roots and `--upstream-ca`, without pins
+    verifier: UpstreamVerifier,
+    tls: TlsConnector,
+    /// Without certificate and host name checks, for `insecure` rules
+    insecure_tls: TlsConnector,
+    pool: Arc<Pool>,
+    nodelay: bool,
+    keepalive: Option<Duration>,
+}
+
+/// What went wrong with the upstream request
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    Dns,
+    Refused,
+    Tls,
+    /// Certificate is not trusted or doesn't match the `pins` of the host
+    Certificate,
+    Timeout,
+    SlowBody,
+    Other,
+}
+
+/// Upstream failure shown to the client as an error page
+#[derive(Debug)]
+pub struct UpstreamError {
+    pub failure: Failure,
+    pub host: String,
+    pub reason: String,
+}
+
+impl UpstreamError {
+    pub fn new(failure: Failure, host: &str, reason: impl ToString) -> Self {
+        Self {
+            failure,
+            host: host.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for UpstreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} error for {}: {}",
+            self.failure, self.host, self.reason
+        )
+    }
+}
+
+impl std::error::Error for UpstreamError {}
+
+impl Client {
+    pub fn new() -> Result<Self, UnifiedError> {
+        let mut roots = RootCertStore::empty();
+        let native = rustls_native_certs::load_native_certs();
+        for err in &native.errors {
+            tracing::warn!("Can't load system certificates: {err}");
+        }
+        roots.add_parsable_certificates(native.certs);
+        if let Some(path) = &CLI.upstream_ca {
+            for cert in load_ca_bundle(path)? {
+                roots.add(cert)?;
+            }
+        }
+        let verifier = UpstreamVerifier::new(roots)?;
+        Ok(Self {
+            tls: connector(verifier.clone()),
+            insecure_tls: connector(UpstreamVerifier {
+                insecure: true,
+                ..verifier.clone()
+            }),
+            verifier,
+            pool: Pool::new(
+                Duration::from_secs(CLI.pool_idle_timeout.into()),
+                CLI.pool_max_idle_per_host,
+            ),
+            nodelay: CLI.tcp_nodelay,
+            keepalive: (CLI.tcp_keepalive > 0)
+                .then(|| Duration::from_secs(CLI.tcp_keepalive.into())),
+        })
+    }
+
+    /// Response and whether the connection was upgraded (WebSocket etc.).
+    /// GET and HEAD without body are retried when the connection is reset before the response,
+    /// any request is resent when a pooled connection turns out to be closed before it was written.
+    pub async fn send_request(
+        &self,
+        mut req: Request<Incoming>,
+        policy: &Policy,
+    ) -> Result<(Response<Incoming>, bool), UnifiedError> {
+        let client_upgrade = hyper::upgrade::on(&mut req);
+        let retryable = is_retryable(&req);
+        let (parts, body) = req.into_parts();
+        let retry_parts = (retryable && policy.retries > 0).then(|| parts.clone());
+
+        let mut result = self
+            .send_once(Request::from_parts(parts, body.boxed()), policy)
+            .await;
+        if let Some(parts) = retry_parts {
+            for attempt in 1..=policy.retries {
+                match &result {
+                    Err(err) if is_reset(err.as_ref()) => {
+                        tracing::debug!("Retry {attempt} of {} after: {err}", parts.uri);
+                    }
+                    _ => break,
+                }
+                let body = Empty::new().map_err(|never| match never {}).boxed();
+                result = self
+                    .send_once(Request::from_parts(parts.clone(), body), policy)
+                    .await;
+            }
+        }
+        let mut res = result?;
+
+        let upgraded = res.status() == StatusCode::SWITCHING_PROTOCOLS;
+        if upgraded {
+            let server_upgrade = hyper::upgrade::on(&mut res);
+            tokio::spawn(async move {
+                match tokio::try_join!(client_upgrade, server_upgrade) {
+                    Ok((client, server)) => {
+                        let _ = tokio::io::copy_bidirectional(
+                            &mut TokioIo::new(client),
+                            &mut TokioIo::new(server),
+                        )
+                        .await;
+                    }
+                    Err(err) => tracing::debug!("Upgrade failed: {err}"),
+                }
+            });
+        }
+        Ok((res, upgraded))
+    }
+
+    async fn send_once(
+        &self,
+        mut req: Request<RequestBody>,
+        policy: &Policy,
+    ) -> Result<Response<Incoming>, UnifiedError> {
+        let uri = req.uri().clone();
+        let host = uri.host().ok_or("Request without host")?.to_string();
+        let https = uri.scheme() == Some(&Scheme::HTTPS);
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let upstream = upstream::for_host(&host, policy.direct);
+        // plain http отдаём прокси как есть, с абсолютным URI
+        let proxied = upstream.filter(|u| !https && u.scheme == UpstreamScheme::Http);
+        let key = PoolKey {
+            https,
+            host: host.clone(),
+            port,
+            direct: policy.direct,
+            insecure: policy.insecure,
+            pins: policy.pins.clone(),
+        };
+
+        let (mut sender, reused) = match self.pool.get(&key) {
+            Some(sender) => (sender, true),
+            None => (
+                self.open(&key, upstream, proxied.is_some(), policy).await?,
+                false,
+            ),
+        };
+        if let Some(upstream) = proxied
+            && let Some(auth) = upstream.basic_auth()
+        {
+            req.headers_mut()
+                .insert(PROXY_AUTHORIZATION, HeaderValue::from_str(&auth)?);
+        }
+        if proxied.is_none() && !sender.is_h2() {
+            origin_form(&mut req, &uri)?;
+        }
+        let headers_timeout = (policy.headers_timeout, "response headers");
+        let send = async { Ok::<_, UnifiedError>(sender.send(req).await) };
+        let res = match phase(&host, headers_timeout, send).await? {
+            Ok(res) => res,
+            // сервер закрыл соединение, пока оно лежало в пуле: запрос никуда не ушёл,
+            // поэтому его можно отправить по новому соединению при любом методе
+            Err(SendError {
+                error,
+                unsent: Some(mut req),
+            }) if reused => {
+                tracing::debug!("Pooled connection to {host} is closed, reconnecting: {error}");
+                sender = self.open(&key, upstream, proxied.is_some(), policy).await?;
+                *req.uri_mut() = uri.clone();
+                if proxied.is_none() && !sender.is_h2() {
+                    origin_form(&mut req, &uri)?;
+                }
+                let send = async { Ok::<_, UnifiedError>(sender.send(req).await) };
+                phase(&host, headers_timeout, send)
+                    .await?
+                    .map_err(|err| err.error)?
+            }
+            Err(err) => return Err(err.error),
+        };
+        if let Sender::Http1(sender) = sender
+            && res.status() != StatusCode::SWITCHING_PROTOCOLS
+        {
+            self.pool.put(key, sender);
+        }
+        Ok(res)
+    }
+
+    /// New connection to the site or through the upstream proxy, counted by the pool
+    async fn open(
+        &self,
+        key: &PoolKey,
+        upstream: Option<&UpstreamProxy>,
+        proxied: bool,
+        policy: &Policy,
+    ) -> Result<Sender, UnifiedError> {
+        let host = key.host.as_str();
+        let tcp = async {
+            match upstream {
+                Some(upstream) if proxied => {
+                    TcpStream::connect(&upstream.addr).await.map_err(|e| {
+                        UnifiedError::from(format!(
+                            "Can't connect to upstream proxy {}: {e}",
+                            upstream.addr
+                        ))
+                    })
+                }
+                Some(upstream) => upstream.connect(host, key.port).await,
+                None => Ok(connect(host, key.port).await?),
+            }
+        };
+        let stream = phase(host, (policy.connect_timeout, "connect"), tcp).await?;
+        self.tune(&stream);
+        let sender = if key.https && !proxied {
+            let stream = self.tls_connect(host, stream, policy).await?;
+            let h2 = stream.get_ref().1.alpn_protocol() == Some(b"h2");
+            handshake(stream, h2).await?
+        } else {
+            handshake(stream, false).await?
+        };
+        self.pool.opened(key, &sender);
+        Ok(sender)
+    }
+
+    fn tune(&self, stream: &TcpStream) {
+        if let Err(err) = stream.set_nodelay(self.nodelay) {
+            tracing::debug!("Can't set TCP_NODELAY: {err}");
+        }
+        if let Some(time) = self.keepalive {
+            let keepalive = TcpKeepalive::new().with_time(time);
+            if let Err(err) = SockRef::from(stream).set_tcp_keepalive(&keepalive) {
+                tracing::debug!("Can't set TCP keepalive: {err}");
+            }
+        }
+    }
+
+    async fn tls_connect<S>(
+        &self,
+        host: &str,
+        stream: S,
+        policy: &Policy,
+    ) -> Result<TlsStream<S>, UnifiedError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let pinned;
+        let connector = if !policy.pins.is_empty() {
+            pinned = connector(UpstreamVerifier {
+                insecure: policy.insecure,
+                pins: policy.pins.clone(),
+                ..self.verifier.clone()
+            });
+            &pinned
+        } else if policy.insecure {
+            &self.insecure_tls
+        } else {
+            &self.tls
+        };
+        let name = ServerName::try_from(bare_host(host).to_string())
+            .map_err(|e| UpstreamError::new(Failure::Tls, host, e))?;
+        let handshake = async {
+            connector
+                .connect(name, stream)
+                .await
+                .map_err(|err| tls_error(host, err))
+        };
+        phase(host, (policy.tls_timeout, "TLS handshake"), handshake).await
+    }
+}
+
+/// Checks the upstream certificate unless the host is `insecure`, then its `pins`
+#[derive(Debug, Clone)]
+struct UpstreamVerifier {
+    webpki: Arc<WebPkiServerVerifier>,
+    /// Roots of `webpki`, searched for the pinned keys
+    roots: Arc<RootCertStore>,
+    algorithms: WebPkiSupportedAlgorithms,
+    insecure: bool,
+    pins: Vec<String>,
+}
+
+impl ServerCertVerifier for UpstreamVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if !self.insecure {
+            self.webpki.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?;
+        }
+        if !self.pins.is_empty() {
+            self.check_pins(end_entity, intermediates, now)?;
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.webpki.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.webpki.supported_verify_schemes()
+    }
+}
+
+impl UpstreamVerifier {
+    fn new(roots: RootCertStore) -> Result<Self, UnifiedError> {
+        let roots = Arc::new(roots);
+        let webpki = WebPkiServerVerifier::builder(roots.clone()).build()?;
+        // builder выше ставит провайдер по умолчанию, если его не было
+        let algorithms = CryptoProvider::get_default()
+            .ok_or("No default TLS crypto provider")?
+            .signature_verification_algorithms;
+        Ok(Self {
+            webpki,
+            roots,
+            algorithms,
+            insecure: false,
+            pins: Vec::new(),
+        })
+    }
+
+    /// SHA-256 of the public key (SPKI) of the end-entity certificate must match one of `pins`
+    /// (`sha256/BASE64` or just `BASE64`), or, unless `insecure`, of a certificate it is verified to chain to.
+    /// Intermediates sent by the server count only when the end-entity certificate is signed through them.
+    fn check_pins(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        now: UnixTime,
+    ) -> Result<(), rustls::Error> {
+        let cert = ParsedCertificate::try_from(end_entity)?;
+        let leaf = spki_pin(&cert.subject_public_key_info());
+        if self.is_pinned(&leaf) {
+            return Ok(());
+        }
+        if !self.insecure {
+            // пиненные ключи становятся единственными корнями, путь до них проверяется подписями
+            let mut pinned = RootCertStore::empty();
+            for der in intermediates {
+                let spki = ParsedCertificate::try_from(der)?.subject_public_key_info();
+                if self.is_pinned(&spki_pin(&spki)) {
+                    pinned.add(der.clone())?;
+                }
+            }
+            pinned.roots.extend(
+                self.roots
+                    .roots
+                    .iter()
+                    .filter(|anchor| self.is_pinned(&spki_pin(&anchor_spki(anchor))))
+                    .cloned(),
+            );
+            if !pinned.is_empty()
+                && verify_server_cert_signed_by_trust_anchor(
+                    &cert,
+                    &pinned,
+                    intermediates,
+                    now,
+                    self.algorithms.all,
+                )
+                .is_ok()
+            {
+                return Ok(());
+            }
+        }
+        let reason = format!("public key sha256/{leaf} is not pinned");
+        Err(rustls::Error::InvalidCertificate(CertificateError::Other(
+            OtherError(Arc::from(UnifiedError::from(reason))),
+        )))
+    }
+
+    fn is_pinned(&self, pin: &str) -> bool {
+        self.pins
+            .iter()
+            .any(|p| p.trim_ascii().trim_start_matches("sha256/") == pin)
+    }
+}
+
+fn connector(verifier: UpstreamVerifier) -> TlsConnector {
+    let mut config = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    config.alpn_protocols = if CLI.upstream_http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    TlsConnector::from(Arc::new(config))
+}
+
+/// Timeout of `secs` seconds, 0 - no timeout
+pub fn timeout_secs(secs: u32) -> Duration {
+    if secs == 0 {
+        // tokio ставит таймер на самый дальний срок
+        Duration::MAX
+    } else {
+        Duration::from_secs(secs.into())
+    }
+}
+
+/// `fut` limited by the phase timeout `(seconds, name)`
+pub async fn phase<T, E>(
+    host: &str,
+    (secs, name): (u32, &str),
+    fut: impl Future<Output = Result<T, E>>,
+) -> Result<T, UnifiedError>
+where
+    E: Into<UnifiedError>,
+{
+    match timeout(timeout_secs(secs), fut).await {
+        Ok(res) => res.map_err(Into::into),
+        Err(_) => Err(UpstreamError::new(
+            Failure::Timeout,
+            host,
+            format!("{name} timed out after {secs}s"),
+        )
+        .into()),
+    }
+}
+
+// GET и HEAD без тела можно отправить заново целиком
+fn is_retryable<B: Body>(req: &Request<B>) -> bool {
+    matches!(*req.method(), Method::GET | Method::HEAD) && req.body().is_end_stream()
+}
+
+// соединение оборвалось до ответа, запрос без тела можно повторить
+fn is_reset(err: &(dyn StdError + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if err
+            .downcast_ref::<hyper::Error>()
+            .is_some_and(|e| e.is_incomplete_message() || e.is_canceled())
+        {
+            return true;
+        }
+        if let Some(err) = err.downcast_ref::<io::Error>()
+            && matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+            )
+        {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+/// Direct connection, DNS and TCP failures are told apart for the error page
+async fn connect(host: &str, port: u16) -> Result<TcpStream, UpstreamError> {
+    let addrs = lookup_host((bare_host(host), port))
+        .await
+        .map_err(|e| UpstreamError::new(Failure::Dns, host, e))?;
+    let mut last_err = None;
+    for addr in addrs {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(match last_err {
+        Some(err) => UpstreamError::new(
+            match err.kind() {
+                io::ErrorKind::ConnectionRefused => Failure::Refused,
+                io::ErrorKind::TimedOut => Failure::Timeout,
+                _ => Failure::Other,
+            },
+            host,
+            err,
+        ),
+        None => UpstreamError::new(Failure::Dns, host, "no addresses"),
+    })
+}
+
+// HTTP/1.1 к origin серверу: путь вместо абсолютного URI и обязательный Host
+fn origin_form(req: &mut Request<RequestBody>, uri: &Uri) -> Result<(), UnifiedError> {
+    if !req.headers().contains_key(HOST)
+        && let Some(authority) = uri.authority()
+    {
+        req.headers_mut()
+            .insert(HOST, HeaderValue::from_str(authority.as_str())?);
+    }
+    *req.uri_mut() = uri.path_and_query().map_or("/", |p| p.as_str()).parse()?;
+    Ok(())
+}
+
+// IPv6 без скобок для TcpStream::connect и SNI
+fn bare_host(host: &str) -> &str {
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+// rustls отдаёт свою ошибку внутри io::Error, отказ по pins приходит как Other с нашим текстом
+fn tls_error(host: &str, err: io::Error) -> UnifiedError {
+    let (failure, reason) = match err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<rustls::Error>())
+    {
+        Some(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(pin)))) => {
+            (Failure::Certificate, pin.to_string())
+        }
+        Some(rustls::Error::InvalidCertificate(_)) => (Failure::Certificate, err.to_string()),
+        _ => (Failure::Tls, err.to_string()),
+    };
+    UpstreamError::new(failure, host, reason).into()
+}
+
+fn spki_pin(spki: &[u8]) -> String {
+    STANDARD.encode(Sha256::digest(spki))
+}
+
+// TrustAnchor хранит SPKI без внешнего SEQUENCE
+fn anchor_spki(anchor: &TrustAnchor<'_>) -> Vec<u8> {
+    let content = anchor.subject_public_key_info.as_ref();
+    let len = content.len().to_be_bytes();
+    let len = &len[len.iter().take_while(|&&b| b == 0).count()..];
+    let mut der = vec![0x30];
+    if content.len() < 0x80 {
+        der.push(content.len() as u8);
+    } else {
+        der.push(0x80 | len.len() as u8);
+        der.extend_from_slice(len);
+    }
+    der.extend_from_slice(content);
+    der
+}
+
+/// Certificates of a PEM bundle
+fn load_ca_bundle(path: &Path) -> Result<Vec<CertificateDer<'static>>, UnifiedError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Can't read CA bundle {}: {e}", path.display()))?;
+    if certs.is_empty() {
+        return Err(format!("No certificates in {}", path.display()).into());
+    }
+    Ok(certs)
+}
+
+async fn handshake<S>(io: S, h2: bool) -> Result<Sender, UnifiedError>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(io);
+    Ok(if h2 {
+        let (sender, conn) = http2::handshake(TokioExecutor::new(), io).await?;
+        tokio::spawn(async move {
+            if let Err(err) = conn.await {
+                tracing::debug!("Upstream connection closed: {err}");
+            }
+        });
+        Sender::Http2(sender)
+    } else {
+        let (sender, conn) = http1::Builder::new()
+            .preserve_header_case(true)
+            .title_case_headers(true)
+            .handshake(io)
+            .await?;
+        tokio::spawn(async move {
+            if let Err(err) = conn.with_upgrades().await {
+                tracing::debug!("Upstream connection closed: {err}");
+            }
+        });
+        Sender::Http1(sender)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair};
+
+    fn ca(
+        issuer: Option<&Issuer<'static, KeyPair>>,
+    ) -> (Issuer<'static, KeyPair>, CertificateDer<'static>) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let cert = match issuer {
+            Some(issuer) => params.signed_by(&key, issuer),
+            None => params.self_signed(&key),
+        }
+        .unwrap();
+        (Issuer::new(params, key), cert.der().clone())
+    }
+
+    fn leaf(issuer: &Issuer<'static, KeyPair>) -> CertificateDer<'static> {
+        let key = KeyPair::generate().unwrap();
+        let params = CertificateParams::new(vec!["example.com".to_string()]).unwrap();
+        params.signed_by(&key, issuer).unwrap().der().clone()
+    }
+
+    fn pin(cert: &CertificateDer<'_>) -> String {
+        let spki = ParsedCertificate::try_from(cert)
+            .unwrap()
+            .subject_public_key_info();
+        format!("sha256/{}", spki_pin(&spki))
+    }
+
+    fn verify(
+        roots: &[&CertificateDer<'_>],
+        insecure: bool,
+        pins: &[&CertificateDer<'_>],
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+    ) -> bool {
+        let mut store = RootCertStore::empty();
+        for root in roots {
+            store.add((*root).clone()).unwrap();
+        }
+        let verifier = UpstreamVerifier {
+            insecure,
+            pins: pins.iter().map(|c| pin(c)).collect(),
+            ..UpstreamVerifier::new(store).unwrap()
+        };
+        let name = ServerName::try_from("example.com").unwrap();
+        verifier
+            .verify_server_cert(end_entity, intermediates, &name, &[], UnixTime::now())
+            .is_ok()
+    }
+
+    #[test]
+    fn pinned_key_on_the_verified_path() {
+        let (root, root_der) = ca(None);
+        let (intermediate, intermediate_der) = ca(Some(&root));
+        let end_entity = leaf(&intermediate);
+        let chain = [intermediate_der.clone()];
+        assert!(verify(
+            &[&root_der],
+            false,
+            &[&intermediate_der],
+            &end_entity,
+            &chain
+        ));
+        assert!(verify(
+            &[&root_der],
+            false,
+            &[&root_der],
+            &end_entity,
+            &chain
+        ));
+        assert!(verify(
+            &[&root_der],
+            false,
+            &[&end_entity],
+            &end_entity,
+            &chain
+        ));
+    }
+
+    #[test]
+    fn appended_pinned_certificate_is_not_a_path() {
+        let (_, pinned_der) = ca(None);
+        let (other, other_der) = ca(None);
+        let end_entity = leaf(&other);
+        let chain = [pinned_der.clone()];
+        let roots = [&pinned_der, &other_der];
+        assert!(verify(&roots, false, &[], &end_entity, &chain));
+        assert!(!verify(&roots, false, &[&pinned_der], &end_entity, &chain));
+    }
+
+    #[test]
+    fn insecure_accepts_only_the_end_entity_pin() {
+        let (_, pinned_der) = ca(None);
+        let (forger, _) = ca(None);
+        let end_entity = leaf(&forger);
+        let chain = [pinned_der.clone()];
+        let roots = [&pinned_der];
+        assert!(!verify(&roots, true, &[&pinned_der], &end_entity, &chain));
+        assert!(verify(&roots, true, &[&end_entity], &end_entity, &chain));
+        assert!(!verify(&roots, false, &[&end_entity], &end_entity, &chain));
+    }
+
+    fn run(test: impl Future<Output = ()>) {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(test);
+    }
+
+    #[test]
+    fn zero_timeout_is_no_timeout() {
+        assert_eq!(timeout_secs(0), Duration::MAX);
+        assert_eq!(timeout_secs(5), Duration::from_secs(5));
+        run(async {
+            let slow = async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Ok::<_, io::Error>(42)
+            };
+            assert_eq!(
+                phase("example.com", (0, "connect"), slow).await.unwrap(),
+                42
+            );
+
+            let never = std::future::pending::<Result<(), io::Error>>();
+            let err = phase("example.com", (1, "connect"), never)
+                .await
+                .unwrap_err();
+            let err = err.downcast_ref::<UpstreamError>().unwrap();
+            assert_eq!(err.failure, Failure::Timeout);
+            assert_eq!(err.reason, "connect timed out after 1s");
+        });
+    }
+
+    #[test]
+    fn only_bodyless_get_and_head_are_retried_after_reset() {
+        let req = |method: Method, body: &'static str| {
+            Request::builder()
+                .method(method)
+                .body(http_body_util::Full::new(bytes::Bytes::from_static(
+                    body.as_bytes(),
+                )))
+                .unwrap()
+        };
+        assert!(is_retryable(&req(Method::GET, "")));
+        assert!(is_retryable(&req(Method::HEAD, "")));
+        assert!(!is_retryable(&req(Method::GET, "q=1")));
+        assert!(!is_retryable(&req(Method::POST, "")));
+
+        let reset = |kind: io::ErrorKind| {
+            let err: UnifiedError = io::Error::from(kind).into();
+            is_reset(err.as_ref())
+        };
+        assert!(reset(io::ErrorKind::ConnectionReset));
+        assert!(reset(io::ErrorKind::UnexpectedEof));
+        assert!(!reset(io::ErrorKind::TimedOut));
+        let timeout = UpstreamError::new(Failure::Timeout, "example.com", "slow");
+        assert!(!is_reset(&timeout));
+    }
+}
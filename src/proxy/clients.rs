@@ -0,0 +1,395 @@
+use crate::{cli::Cli, initable_static, maybe::UnifiedError};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    task::{Context, Poll, ready},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Sleep, sleep},
+};
+
+initable_static! {
+    CLIENT_LIMITS = reloadable |cli: &Cli| -> Result<ClientLimits, UnifiedError> {
+        Ok(ClientLimits {
+            allow: Cidr::parse_list(cli.allow_clients.as_deref())?,
+            deny: Cidr::parse_list(cli.deny_clients.as_deref())?,
+            max_connections: cli.client_max_connections,
+            bandwidth: cli.client_bandwidth,
+        })
+    };
+}
+
+/// Client address, stored in the request extensions
+#[derive(Clone, Copy)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// Network in CIDR notation, a single address is a full-length prefix
+pub struct Cidr {
+    net: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    fn parse(s: &str) -> Result<Self, UnifiedError> {
+        let (addr, prefix) = s.split_once('/').unwrap_or((s, ""));
+        let net: IpAddr = addr
+            .trim_ascii()
+            .parse()
+            .map_err(|e| format!("Invalid network '{s}': {e}"))?;
+        let max = if net.is_ipv4() { 32 } else { 128 };
+        let prefix = if prefix.is_empty() {
+            max
+        } else {
+            prefix.trim_ascii().parse()?
+        };
+        if prefix > max {
+            return Err(format!("Invalid prefix length in '{s}'").into());
+        }
+        // адреса клиентов сравниваются в каноническом виде, ::ffff:a.b.c.d/N приводится к IPv4
+        if let IpAddr::V6(v6) = net
+            && let Some(v4) = v6.to_ipv4_mapped()
+            && prefix >= 96
+        {
+            return Ok(Self {
+                net: IpAddr::V4(v4),
+                prefix: prefix - 96,
+            });
+        }
+        Ok(Self { net, prefix })
+    }
+
+    fn parse_list(list: Option<&str>) -> Result<Vec<Self>, UnifiedError> {
+        list.unwrap_or_default()
+            .split(',')
+            .filter(|s| !s.trim_ascii().is_empty())
+            .map(Self::parse)
+            .collect()
+    }
+
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.net, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+pub struct ClientLimits {
+    allow: Vec<Cidr>,
+    deny: Vec<Cidr>,
+    max_connections: usize,
+    /// Bytes per second in each direction, 0 - unlimited
+    bandwidth: usize,
+}
+
+impl ClientLimits {
+    /// Only `--allow-clients` networks can connect
+    pub fn has_allow_list(&self) -> bool {
+        !self.allow.is_empty()
+    }
+
+    fn is_allowed(&self, ip: IpAddr) -> bool {
+        !self.deny.iter().any(|c| c.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|c| c.contains(ip)))
+    }
+}
+
+/// Counters of one client IP
+#[derive(Clone, Copy)]
+pub struct ClientStats {
+    pub connections: usize,
+    pub requests: u64,
+    /// Bytes read from and written to the client connections
+    pub bytes: u64,
+}
+
+/// State of a client IP shared by its connections, dropped from the map with the last one
+struct ClientState {
+    connections: AtomicUsize,
+    requests: AtomicU64,
+    bytes: AtomicU64,
+    /// `--client-bandwidth` buckets by [`Direction`]
+    buckets: [Mutex<Bucket>; 2],
+}
+
+impl Default for ClientState {
+    fn default() -> Self {
+        Self {
+            connections: AtomicUsize::new(0),
+            requests: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            buckets: [(); 2].map(|_| {
+                Mutex::new(Bucket {
+                    tokens: 0.0,
+                    refilled: Instant::now(),
+                })
+            }),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Direction {
+    /// From the client
+    Read,
+    /// To the client
+    Write,
+}
+
+// token bucket, уходит в минус на прочитанных сверх лимита байтах
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    // Ok - можно передавать, Err - сколько ждать пополнения
+    fn acquire(&mut self, rate: f64) -> Result<(), Duration> {
+        let now = Instant::now();
+        // запас не больше секунды трафика
+        self.tokens =
+            (self.tokens + now.duration_since(self.refilled).as_secs_f64() * rate).min(rate);
+        self.refilled = now;
+        if self.tokens > 0.0 {
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+// общий замок только на открытие и закрытие соединений, счётчики и bucket у каждого IP свои
+static CLIENTS: LazyLock<Mutex<HashMap<IpAddr, Arc<ClientState>>>> =
+    LazyLock::new(Default::default);
+
+/// Accepted connection, counted against `--client-max-connections` while alive
+pub struct ClientConnection(IpAddr, Arc<ClientState>);
+
+impl ClientConnection {
+    /// `None` if the peer is not allowed or has too many connections
+    pub fn open(peer: SocketAddr) -> Option<Self> {
+        let limits = CLIENT_LIMITS::get();
+        let ip = peer.ip().to_canonical();
+        if !limits.is_allowed(ip) {
+            tracing::debug!("Rejected connection from {peer}: not in allowed networks");
+            return None;
+        }
+        let mut clients = CLIENTS.lock();
+        let client = clients.entry(ip).or_default();
+        let connections = client.connections.load(Ordering::Relaxed);
+        if limits.max_connections > 0 && connections >= limits.max_connections {
+            tracing::warn!(
+                "Rejected connection from {peer}: {connections} connections already open"
+            );
+            return None;
+        }
+        client.connections.fetch_add(1, Ordering::Relaxed);
+        Some(ClientConnection(ip, client.clone()))
+    }
+}
+
+impl Drop for ClientConnection {
+    fn drop(&mut self) {
+        // счётчик меняется под замком карты, иначе open мог бы взять удаляемую запись
+        let mut clients = CLIENTS.lock();
+        if self.1.connections.fetch_sub(1, Ordering::Relaxed) == 1 {
+            clients.remove(&self.0);
+        }
+    }
+}
+
+pub fn count_request(peer: SocketAddr) {
+    if let Some(client) = CLIENTS.lock().get(&peer.ip().to_canonical()) {
+        client.requests.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Connected clients sorted by transferred bytes
+pub fn snapshot() -> Vec<(IpAddr, ClientStats)> {
+    let mut clients: Vec<_> = CLIENTS
+        .lock()
+        .iter()
+        .map(|(ip, c)| {
+            let stats = ClientStats {
+                connections: c.connections.load(Ordering::Relaxed),
+                requests: c.requests.load(Ordering::Relaxed),
+                bytes: c.bytes.load(Ordering::Relaxed),
+            };
+            (*ip, stats)
+        })
+        .collect();
+    clients.sort_by_key(|(_, c)| std::cmp::Reverse(c.bytes));
+    clients
+}
+
+impl ClientState {
+    fn acquire(&self, direction: Direction) -> Result<(), Duration> {
+        let rate = CLIENT_LIMITS::get().bandwidth as f64;
+        if rate == 0.0 {
+            return Ok(());
+        }
+        self.buckets[direction as usize].lock().acquire(rate)
+    }
+
+    fn consume(&self, direction: Direction, bytes: usize) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        if CLIENT_LIMITS::get().bandwidth > 0 {
+            self.buckets[direction as usize].lock().tokens -= bytes as f64;
+        }
+    }
+}
+
+/// Client stream limited by `--client-bandwidth` (shared by all connections of the IP)
+pub struct Throttled<S> {
+    inner: S,
+    client: Arc<ClientState>,
+    /// Waits for the bucket refill by [`Direction`]
+    delays: [Option<Pin<Box<Sleep>>>; 2],
+}
+
+impl<S> Throttled<S> {
+    pub fn new(inner: S, peer: SocketAddr) -> Self {
+        // запись есть, пока жив ClientConnection этого соединения
+        let client = CLIENTS
+            .lock()
+            .get(&peer.ip().to_canonical())
+            .cloned()
+            .unwrap_or_default();
+        Self {
+            inner,
+            client,
+            delays: [None, None],
+        }
+    }
+
+    fn poll_budget(&mut self, cx: &mut Context<'_>, direction: Direction) -> Poll<()> {
+        let delay = &mut self.delays[direction as usize];
+        loop {
+            if let Some(sleep) = delay {
+                ready!(sleep.as_mut().poll(cx));
+                *delay = None;
+            }
+            match self.client.acquire(direction) {
+                Ok(()) => return Poll::Ready(()),
+                Err(wait) => *delay = Some(Box::pin(sleep(wait))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Throttled<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        ready!(self.poll_budget(cx, Direction::Read));
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.client
+            .consume(Direction::Read, buf.filled().len() - before);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Throttled<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.poll_budget(cx, Direction::Write));
+        let n = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.client.consume(Direction::Write, n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contains(net: &str, ip: &str) -> bool {
+        Cidr::parse(net).unwrap().contains(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn directions_have_own_buckets() {
+        let client = ClientState::default();
+        client.buckets[Direction::Read as usize].lock().tokens = 1.0;
+        client.buckets[Direction::Write as usize].lock().tokens = -1000.0;
+        let rate = 100.0;
+        assert!(
+            client.buckets[Direction::Write as usize]
+                .lock()
+                .acquire(rate)
+                .is_err()
+        );
+        assert!(
+            client.buckets[Direction::Read as usize]
+                .lock()
+                .acquire(rate)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn zero_prefix_matches_the_whole_family() {
+        assert!(contains("0.0.0.0/0", "203.0.113.7"));
+        assert!(contains("0.0.0.0/0", "::ffff:203.0.113.7"));
+        assert!(!contains("0.0.0.0/0", "2001:db8::1"));
+        assert!(contains("::/0", "2001:db8::1"));
+        assert!(!contains("::/0", "203.0.113.7"));
+    }
+
+    #[test]
+    fn full_prefix_matches_one_address() {
+        assert!(contains("2001:db8::1/128", "2001:db8::1"));
+        assert!(!contains("2001:db8::1/128", "2001:db8::2"));
+        assert!(contains("2001:db8::1", "2001:db8::1"));
+        assert!(contains("192.168.1.1", "192.168.1.1"));
+        assert!(!contains("192.168.1.1/32", "192.168.1.2"));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses() {
+        assert!(contains("192.168.1.0/24", "::ffff:192.168.1.5"));
+        assert!(!contains("192.168.1.0/24", "::ffff:192.168.2.5"));
+        assert!(contains("::ffff:192.168.1.0/120", "192.168.1.5"));
+        assert!(contains("::ffff:192.168.1.0/120", "::ffff:192.168.1.5"));
+        assert!(!contains("::ffff:192.168.1.0/120", "192.168.2.5"));
+    }
+
+    #[test]
+    fn invalid_prefixes() {
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(Cidr::parse("::/129").is_err());
+        assert!(Cidr::parse("10.0.0.0/x").is_err());
+        assert!(Cidr::parse("example.com").is_err());
+    }
+}
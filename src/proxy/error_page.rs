@@ -0,0 +1,100 @@
+use crate::{
+    maybe::UnifiedError,
+    proxy::{
+        bytes_ext::BytesExt,
+        client::{Failure, UpstreamError},
+        response_ext::BoxedResponse,
+        status::escape,
+    },
+};
+use bytes::Bytes;
+use hyper::{Request, StatusCode, Uri, Version};
+
+/// Page for a failed upstream request, prepared before the request is sent
+pub struct ErrorPage {
+    version: Version,
+    uri: Uri,
+    /// Browser navigation gets HTML, subresources and API calls plain text
+    document: bool,
+}
+
+impl ErrorPage {
+    pub fn new<B>(req: &Request<B>, accept: &str) -> Self {
+        Self {
+            version: req.version(),
+            uri: req.uri().clone(),
+            document: accept.starts_with("text/html"),
+        }
+    }
+
+    pub fn response(&self, err: &UnifiedError) -> BoxedResponse {
+        let host = self.uri.host().unwrap_or_default();
+        let (failure, reason) = match err.downcast_ref::<UpstreamError>() {
+            Some(err) => (err.failure, err.reason.clone()),
+            None => (classify(err), err.to_string()),
+        };
+        tracing::warn!("{} {}: {reason}", title(failure), self.uri);
+
+        let status = match failure {
+            Failure::Timeout | Failure::SlowBody => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::BAD_GATEWAY,
+        };
+        if self.document {
+            Bytes::from(format!(
+                r#"<!DOCTYPE html><html><head><meta charset="utf-8"><meta name="viewport" content="width=device-width"><title>{title}</title><style>body{{font:16px/1.5 sans-serif;max-width:40em;margin:10vh auto;padding:0 1em;color:#222}}code{{color:#777;word-break:break-all}}a{{display:inline-block;padding:.3em 1.2em;border-radius:4px;background:#36c;color:#fff;text-decoration:none}}</style></head><body><h1>{title}</h1><p>{hint}</p><p><code>{host}: {reason}</code></p><a href="{url}">Retry</a></body></html>"#,
+                title = title(failure),
+                hint = hint(failure),
+                host = escape(host),
+                reason = escape(&reason),
+                url = escape(&self.uri.to_string()),
+            ))
+            .to_response(self.version, status, "text/html; charset=utf-8")
+        } else {
+            Bytes::from(format!("Error: {} ({host}): {reason}.", title(failure))).to_response(
+                self.version,
+                status,
+                "text/plain; charset=utf-8",
+            )
+        }
+    }
+}
+
+fn title(failure: Failure) -> &'static str {
+    match failure {
+        Failure::Dns => "Site not found",
+        Failure::Refused => "Connection refused",
+        Failure::Tls => "Secure connection failed",
+        Failure::Certificate => "Certificate not accepted",
+        Failure::Timeout => "Site is not responding",
+        Failure::SlowBody => "Site is too slow",
+        Failure::Other => "Site is unavailable",
+    }
+}
+
+fn hint(failure: Failure) -> &'static str {
+    match failure {
+        Failure::Dns => "The domain name could not be resolved. Check the address for typos.",
+        Failure::Refused => "The server is reachable but doesn't accept connections on this port.",
+        Failure::Tls => "The TLS handshake with the server failed.",
+        Failure::Certificate => {
+            "The server certificate is not trusted. Add its CA with --upstream-ca, pin it or mark the host insecure."
+        }
+        Failure::Timeout => "The server didn't answer in time.",
+        Failure::SlowBody => "The server stopped sending the page before it was complete.",
+        Failure::Other => "The request to the server failed.",
+    }
+}
+
+// ошибки без UpstreamError: hyper и ввод-вывод через upstream прокси
+fn classify(err: &UnifiedError) -> Failure {
+    if let Some(err) = err.downcast_ref::<hyper::Error>()
+        && err.is_timeout()
+    {
+        return Failure::Timeout;
+    }
+    match err.downcast_ref::<std::io::Error>().map(|e| e.kind()) {
+        Some(std::io::ErrorKind::ConnectionRefused) => Failure::Refused,
+        Some(std::io::ErrorKind::TimedOut) => Failure::Timeout,
+        _ => Failure::Other,
+    }
+}
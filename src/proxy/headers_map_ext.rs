@@ -1,4 +1,4 @@
-use crate::{cli::CLI, dac::DAC};
+use crate::{cli::CLI, reload::ETAG_MARKER};
 use bytes::Bytes;
 use easy_ext::ext;
 use encoding_rs::Encoding;
@@ -6,7 +6,8 @@ use hyper::{
     HeaderMap,
     header::{
         ACCEPT_RANGES, AsHeaderName, CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE, DATE,
-        ETAG, EXPIRES, HeaderValue, IF_MATCH, IF_NONE_MATCH, IntoHeaderName, VARY,
+        ETAG, EXPIRES, HeaderValue, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IntoHeaderName,
+        VARY,
     },
 };
 use std::{str::FromStr, time::SystemTime};
@@ -127,10 +128,8 @@ pub impl HeaderMap<HeaderValue> {
 
     fn inject_etag_marker(&mut self) {
         if let Some(value) = self.get(ETAG) {
-            let marker = DAC
-                .get()
-                .map(|(etag_marker, _)| etag_marker.as_bytes())
-                .unwrap_or("zhlob~~".as_bytes());
+            let marker = ETAG_MARKER::get();
+            let marker = marker.as_bytes();
 
             let etag = value.as_bytes();
             let mut new_etag = Vec::with_capacity(etag.len() + marker.len());
@@ -152,7 +151,17 @@ pub impl HeaderMap<HeaderValue> {
         }
     }
 
+    fn has_current_etag_marker(&self) -> bool {
+        let marker = ETAG_MARKER::get();
+        self.get_all(IF_NONE_MATCH)
+            .iter()
+            .any(|v| memchr::memmem::find(v.as_bytes(), marker.as_bytes()).is_some())
+    }
+
     fn strip_etag_marker(&mut self) {
+        let current_marker = ETAG_MARKER::get();
+        let mut stale = false;
+
         for name in [IF_MATCH, IF_NONE_MATCH] {
             let mut changed = false;
             let mut out = Vec::new();
@@ -182,16 +191,20 @@ pub impl HeaderMap<HeaderValue> {
                     i += prefix_len;
 
                     // 3. Поиск и вырезание маркера
-                    if i < b.len() && b[i..].starts_with(b"zhlob~") {
-                        if let Some(pos) = b[i + 6..].iter().position(|&x| x == b'~') {
-                            // Сбрасываем накопленный кусок до начала маркера
-                            if i > last_copy_pos {
-                                out.extend_from_slice(&b[last_copy_pos..i]);
-                            }
-                            i += 6 + pos + 1;
-                            last_copy_pos = i;
-                            changed = true;
+                    if i < b.len()
+                        && b[i..].starts_with(b"zhlob~")
+                        && let Some(pos) = b[i + 6..].iter().position(|&x| x == b'~')
+                    {
+                        // Сбрасываем накопленный кусок до начала маркера
+                        if i > last_copy_pos {
+                            out.extend_from_slice(&b[last_copy_pos..i]);
                         }
+                        // контент был трансформирован другой версией DAC/настроек
+                        stale |= name == IF_NONE_MATCH
+                            && b[i..i + 6 + pos + 1] != *current_marker.as_bytes();
+                        i += 6 + pos + 1;
+                        last_copy_pos = i;
+                        changed = true;
                     }
 
                     // 4. Пропуск тела ETag до следующего разделителя
@@ -221,6 +234,12 @@ pub impl HeaderMap<HeaderValue> {
                 self.set_unchecked(name, out);
             }
         }
+
+        if stale {
+            // заставляем upstream отдать полный ответ, чтобы перетрансформировать его
+            self.remove(IF_NONE_MATCH);
+            self.remove(IF_MODIFIED_SINCE);
+        }
     }
 
     fn normalize_extra_for_patched_content(&mut self) {
@@ -258,15 +277,15 @@ pub impl HeaderMap<HeaderValue> {
             let server_date = httpdate::parse_http_date(&self.get_safe(DATE))
                 .unwrap_or_else(|_| SystemTime::now());
 
-            if age.is_none() {
-                if let Ok(expires_date) = httpdate::parse_http_date(&self.get_safe(EXPIRES)) {
-                    age = Some(
-                        expires_date
-                            .duration_since(server_date)
-                            .map(|d| d.as_secs() as i64)
-                            .unwrap_or(-1),
-                    )
-                }
+            if age.is_none()
+                && let Ok(expires_date) = httpdate::parse_http_date(&self.get_safe(EXPIRES))
+            {
+                age = Some(
+                    expires_date
+                        .duration_since(server_date)
+                        .map(|d| d.as_secs() as i64)
+                        .unwrap_or(-1),
+                )
             }
 
             let cache_max_age = CLI.cache_max_age as i64;
@@ -328,12 +347,12 @@ pub impl HeaderMap<HeaderValue> {
                 .split(';')
                 .map(str::trim_ascii)
                 .filter_map(|p| {
-                    if p.starts_with(DIR_ATTR) {
-                        Some((CspPriority::ScriptSrcAttr, &p[DIR_ATTR.len()..]))
-                    } else if p.starts_with(DIR_SCRIPT) {
-                        Some((CspPriority::ScriptSrc, &p[DIR_SCRIPT.len()..]))
-                    } else if p.starts_with(DIR_DEFAULT) {
-                        Some((CspPriority::DefaultSrc, &p[DIR_DEFAULT.len()..]))
+                    if let Some(value) = p.strip_prefix(DIR_ATTR) {
+                        Some((CspPriority::ScriptSrcAttr, value))
+                    } else if let Some(value) = p.strip_prefix(DIR_SCRIPT) {
+                        Some((CspPriority::ScriptSrc, value))
+                    } else if let Some(value) = p.strip_prefix(DIR_DEFAULT) {
+                        Some((CspPriority::DefaultSrc, value))
                     } else {
                         None
                     }
@@ -349,15 +368,11 @@ pub impl HeaderMap<HeaderValue> {
                     }
 
                     match &word[..7] {
-                        "'unsafe" => {
-                            if word == "'unsafe-inline'" {
-                                has_unsafe_inline = true;
-                            }
+                        "'unsafe" if word == "'unsafe-inline'" => {
+                            has_unsafe_inline = true;
                         }
-                        "'strict" => {
-                            if word == "'strict-dynamic'" {
-                                return false;
-                            }
+                        "'strict" if word == "'strict-dynamic'" => {
+                            return false;
                         }
                         "'nonce-" | "'sha256" | "'sha384" | "'sha512" => return false,
                         _ => {}
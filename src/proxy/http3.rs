@@ -0,0 +1,230 @@
+use crate::{
+    maybe::UnifiedError,
+    policy::Policy,
+    proxy::{
+        cert::CertifiedKeyDer,
+        clients::ClientConnection,
+        metrics,
+        mitm::{MitmProxy, tunnel_service},
+        status::TunnelGuard,
+    },
+};
+use bytes::{Buf, Bytes};
+use h3::server::{RequestResolver, RequestStream};
+use http_body_util::BodyExt;
+use hyper::{
+    Response, Version,
+    body::{Body, Frame, Incoming},
+    client::conn::http2::SendRequest,
+    http::uri::{Authority, Scheme},
+    server,
+    service::HttpService,
+};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use quinn::crypto::rustls::{HandshakeData, QuicServerConfig};
+use std::{
+    borrow::Borrow,
+    error::Error as StdError,
+    fmt, io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, atomic::Ordering},
+    task::{Context, Poll, ready},
+};
+use tokio_rustls::rustls::{
+    self,
+    crypto::CryptoProvider,
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+use tracing::Instrument;
+
+/// UDP endpoint for `--h3-listen`
+pub fn endpoint<I>(proxy: Arc<MitmProxy<I>>, addr: SocketAddr) -> io::Result<quinn::Endpoint>
+where
+    I: Borrow<rcgen::Issuer<'static, rcgen::KeyPair>> + Send + Sync + 'static,
+{
+    let mut tls = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(CertResolver(proxy)));
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let crypto = QuicServerConfig::try_from(tls).map_err(io::Error::other)?;
+    quinn::Endpoint::server(quinn::ServerConfig::with_crypto(Arc::new(crypto)), addr)
+}
+
+/// Certificate for the SNI of the QUIC handshake. Bypassed hosts get none, so the handshake fails
+/// and browsers fall back to TCP, which is tunneled as is.
+struct CertResolver<I>(Arc<MitmProxy<I>>);
+
+impl<I> fmt::Debug for CertResolver<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CertResolver")
+    }
+}
+
+impl<I> ResolvesServerCert for CertResolver<I>
+where
+    I: Borrow<rcgen::Issuer<'static, rcgen::KeyPair>> + Send + Sync + 'static,
+{
+    fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let name = hello.server_name()?;
+        if Policy::is_bypassed_host(name, None) {
+            return None;
+        }
+        let der = match self.0.get_certified_key(name.to_string()) {
+            Ok(der) => der,
+            Err(err) => {
+                tracing::error!("Failed to create certificate for {name}, {err}");
+                return None;
+            }
+        };
+        let cert = CertifiedKeyDer(der.as_ref());
+        let key = CryptoProvider::get_default()?
+            .key_provider
+            .load_private_key(PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+                cert.key_der().to_vec(),
+            )))
+            .ok()?;
+        Some(Arc::new(CertifiedKey::new(
+            vec![CertificateDer::from(cert.cert_der().to_vec())],
+            key,
+        )))
+    }
+}
+
+/// QUIC connection: HTTP/3 requests are passed to the service over an in-memory HTTP/2 connection,
+/// so they are handled exactly like the intercepted TLS ones
+pub async fn serve<S>(incoming: quinn::Incoming, service: S)
+where
+    S: HttpService<Incoming> + Clone + Send + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    S::ResBody: Send + Sync + 'static,
+    <S::ResBody as Body>::Data: Send,
+    <S::ResBody as Body>::Error: Into<Box<dyn StdError + Send + Sync>>,
+    S::Future: Send,
+{
+    let peer = incoming.remote_address();
+    let Some(_connection) = ClientConnection::open(peer) else {
+        incoming.refuse();
+        return;
+    };
+    let conn = match incoming.await {
+        Ok(conn) => conn,
+        Err(err) => {
+            metrics::TLS_HANDSHAKE_FAILURES.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!("QUIC handshake failed: {err}");
+            return;
+        }
+    };
+    // порт до REDIRECT неизвестен, QUIC почти всегда идёт на 443
+    let authority = conn
+        .handshake_data()
+        .and_then(|data| data.downcast::<HandshakeData>().ok())
+        .and_then(|data| data.server_name)
+        .and_then(|name| format!("{name}:443").parse::<Authority>().ok());
+    let Some(authority) = authority else {
+        tracing::debug!("QUIC connection without server name");
+        return;
+    };
+    let _tunnel = TunnelGuard::open();
+
+    let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+    let service = tunnel_service(service, authority, Scheme::HTTPS, None, peer);
+    tokio::spawn(
+        async move {
+            if let Err(err) = server::conn::http2::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(server_io), service)
+                .await
+            {
+                tracing::debug!("Connection closed: {err}");
+            }
+        }
+        .instrument(tracing::Span::current()),
+    );
+    let sender =
+        match hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(client_io))
+            .await
+        {
+            Ok((sender, connection)) => {
+                tokio::spawn(connection);
+                sender
+            }
+            Err(err) => {
+                tracing::error!("Failed to connect HTTP/3 to the service: {err}");
+                return;
+            }
+        };
+
+    let mut h3_conn = match h3::server::Connection::new(h3_quinn::Connection::new(conn)).await {
+        Ok(h3_conn) => h3_conn,
+        Err(err) => {
+            tracing::debug!("HTTP/3 connection failed: {err}");
+            return;
+        }
+    };
+    loop {
+        match h3_conn.accept().await {
+            Ok(Some(resolver)) => {
+                let sender = sender.clone();
+                tokio::spawn(
+                    async move {
+                        if let Err(err) = serve_request(resolver, sender).await {
+                            tracing::debug!("HTTP/3 request failed: {err}");
+                        }
+                    }
+                    .instrument(tracing::Span::current()),
+                );
+            }
+            Ok(None) => break,
+            Err(err) => {
+                tracing::debug!("HTTP/3 connection closed: {err}");
+                break;
+            }
+        }
+    }
+}
+
+async fn serve_request(
+    resolver: RequestResolver<h3_quinn::Connection, Bytes>,
+    mut sender: SendRequest<RecvBody>,
+) -> Result<(), UnifiedError> {
+    let (mut req, stream) = resolver.resolve_request().await?;
+    let (mut send, recv) = stream.split();
+    *req.version_mut() = Version::HTTP_2;
+
+    let res = sender.send_request(req.map(|()| RecvBody(recv))).await?;
+    let (parts, mut body) = res.into_parts();
+    send.send_response(Response::from_parts(parts, ())).await?;
+    while let Some(frame) = body.frame().await {
+        match frame?.into_data() {
+            Ok(data) => send.send_data(data).await?,
+            Err(frame) => {
+                if let Ok(trailers) = frame.into_trailers() {
+                    send.send_trailers(trailers).await?;
+                }
+            }
+        }
+    }
+    send.finish().await?;
+    Ok(())
+}
+
+/// Request body of the HTTP/3 stream (trailers are dropped)
+struct RecvBody(RequestStream<h3_quinn::RecvStream, Bytes>);
+
+impl Body for RecvBody {
+    type Data = Bytes;
+    type Error = h3::error::StreamError;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        Poll::Ready(match ready!(self.0.poll_recv_data(cx)) {
+            Ok(Some(mut buf)) => Some(Ok(Frame::data(buf.copy_to_bytes(buf.remaining())))),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        })
+    }
+}
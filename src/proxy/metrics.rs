@@ -0,0 +1,256 @@
+use crate::proxy::status::{ACTIVE_TUNNELS, REQUESTS};
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full, combinators::MapFrame};
+use hyper::{
+    Request, Response, StatusCode,
+    body::{Body, Frame, Incoming},
+    header::CONTENT_TYPE,
+    service::service_fn,
+};
+use hyper_util::rt::TokioIo;
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::net::TcpListener;
+
+pub const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// How the proxy answered the request
+#[derive(Clone, Copy)]
+pub enum Outcome {
+    Passthrough,
+    Transformed,
+    Skipped,
+    Fast304,
+    Blocked,
+    Error,
+}
+
+impl Outcome {
+    const ALL: [Outcome; 6] = [
+        Outcome::Passthrough,
+        Outcome::Transformed,
+        Outcome::Skipped,
+        Outcome::Fast304,
+        Outcome::Blocked,
+        Outcome::Error,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Outcome::Passthrough => "passthrough",
+            Outcome::Transformed => "transformed",
+            Outcome::Skipped => "skipped",
+            Outcome::Fast304 => "fast_304",
+            Outcome::Blocked => "blocked",
+            Outcome::Error => "error",
+        }
+    }
+}
+
+static OUTCOMES: [AtomicU64; Outcome::ALL.len()] =
+    [const { AtomicU64::new(0) }; Outcome::ALL.len()];
+
+pub static BYTES_IN: AtomicU64 = AtomicU64::new(0);
+pub static BYTES_OUT: AtomicU64 = AtomicU64::new(0);
+pub static TLS_HANDSHAKE_FAILURES: AtomicU64 = AtomicU64::new(0);
+pub static CERT_CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+pub static CERT_CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+
+pub static TRANSFORM_HTML: Histogram = Histogram::new();
+pub static TRANSFORM_IMAGE: Histogram = Histogram::new();
+
+pub fn count(outcome: Outcome) {
+    OUTCOMES[outcome as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Upstream and delivered body sizes
+pub fn count_bytes(bytes_in: usize, bytes_out: usize) {
+    BYTES_IN.fetch_add(bytes_in as u64, Ordering::Relaxed);
+    BYTES_OUT.fetch_add(bytes_out as u64, Ordering::Relaxed);
+}
+
+/// Passthrough frame, counted as both received and delivered when it's streamed
+pub fn count_frame(frame: &Frame<Bytes>) {
+    if let Some(data) = frame.data_ref() {
+        count_bytes(data.len(), data.len());
+    }
+}
+
+pub type Counted<B> = MapFrame<B, fn(Frame<Bytes>) -> Frame<Bytes>>;
+
+/// Passthrough body counted by [`count_frame`], so aborted and chunked responses are counted right
+pub fn counted<B>(body: B) -> Counted<B>
+where
+    B: Body<Data = Bytes>,
+{
+    fn counted_frame(frame: Frame<Bytes>) -> Frame<Bytes> {
+        count_frame(&frame);
+        frame
+    }
+    body.map_frame(counted_frame as fn(_) -> _)
+}
+
+// верхние границы в секундах
+const BUCKETS: [f64; 10] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, d: Duration) {
+        let secs = d.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|b| secs <= *b) {
+            self.buckets[i].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(d.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (b, n) in BUCKETS.iter().zip(&self.buckets) {
+            cumulative += n.load(Ordering::Relaxed);
+            let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{b}\"}} {cumulative}");
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {count}");
+        let _ = writeln!(
+            out,
+            "{name}_sum{{{labels}}} {}",
+            self.sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(out, "{name}_count{{{labels}}} {count}");
+    }
+}
+
+/// Prometheus text exposition format
+pub fn render() -> String {
+    let mut out = String::with_capacity(4096);
+    macro_rules! metric {
+        ($name:literal, $kind:literal, $help:literal) => {
+            let _ = writeln!(
+                out,
+                concat!("# HELP ", $name, " ", $help, "\n# TYPE ", $name, " ", $kind)
+            );
+        };
+        ($name:literal, $kind:literal, $help:literal, $value:expr) => {
+            metric!($name, $kind, $help);
+            let _ = writeln!(out, concat!($name, " {}"), $value);
+        };
+    }
+
+    metric!("zhlob_requests_total", "counter", "Requests by outcome");
+    for o in Outcome::ALL {
+        let _ = writeln!(
+            out,
+            "zhlob_requests_total{{outcome=\"{}\"}} {}",
+            o.label(),
+            OUTCOMES[o as usize].load(Ordering::Relaxed)
+        );
+    }
+    metric!(
+        "zhlob_http_requests_total",
+        "counter",
+        "All requests including mitm.it pages",
+        REQUESTS.load(Ordering::Relaxed)
+    );
+    metric!(
+        "zhlob_transform_duration_seconds",
+        "histogram",
+        "Time of the HTML and image transformation"
+    );
+    TRANSFORM_HTML.render(
+        &mut out,
+        "zhlob_transform_duration_seconds",
+        "kind=\"html\"",
+    );
+    TRANSFORM_IMAGE.render(
+        &mut out,
+        "zhlob_transform_duration_seconds",
+        "kind=\"image\"",
+    );
+    metric!(
+        "zhlob_body_bytes_total",
+        "counter",
+        "Body bytes received from upstream and sent to clients"
+    );
+    let _ = writeln!(
+        out,
+        "zhlob_body_bytes_total{{direction=\"in\"}} {}\nzhlob_body_bytes_total{{direction=\"out\"}} {}",
+        BYTES_IN.load(Ordering::Relaxed),
+        BYTES_OUT.load(Ordering::Relaxed)
+    );
+    metric!(
+        "zhlob_tls_handshake_failures_total",
+        "counter",
+        "Failed TLS handshakes with clients of MITM tunnels",
+        TLS_HANDSHAKE_FAILURES.load(Ordering::Relaxed)
+    );
+    metric!(
+        "zhlob_cert_cache_total",
+        "counter",
+        "Certificate cache lookups"
+    );
+    let _ = writeln!(
+        out,
+        "zhlob_cert_cache_total{{result=\"hit\"}} {}\nzhlob_cert_cache_total{{result=\"miss\"}} {}",
+        CERT_CACHE_HITS.load(Ordering::Relaxed),
+        CERT_CACHE_MISSES.load(Ordering::Relaxed)
+    );
+    metric!(
+        "zhlob_active_tunnels",
+        "gauge",
+        "Open CONNECT tunnels",
+        ACTIVE_TUNNELS.load(Ordering::Relaxed)
+    );
+    out
+}
+
+/// Separate listener for `--metrics-listen`
+pub async fn serve(listener: TcpListener) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                tracing::warn!("Failed to accept metrics connection: {}", err);
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let service = service_fn(|req: Request<Incoming>| async move {
+                let res = if req.uri().path() == "/metrics" {
+                    Response::builder()
+                        .header(CONTENT_TYPE, CONTENT_TYPE_TEXT)
+                        .body(Full::new(Bytes::from(render())))
+                } else {
+                    Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Full::new(Bytes::new()))
+                };
+                Ok::<_, Infallible>(res.unwrap_or_default())
+            });
+            if let Err(err) = hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                tracing::debug!("Metrics connection closed: {}", err);
+            }
+        });
+    }
+}
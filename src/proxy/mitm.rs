@@ -2,21 +2,48 @@ use http_body_util::{BodyExt, Empty, combinators::BoxBody};
 use hyper::{
     Method, Request, Response, StatusCode,
     body::{Body, Incoming},
+    header::HOST,
+    http::uri::{Authority, Scheme},
     server,
-    service::{HttpService, service_fn},
+    service::{HttpService, Service, service_fn},
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
 };
-use hyper_util::rt::{TokioExecutor, TokioIo};
 use sled::Db;
-use std::{borrow::Borrow, error::Error as StdError, future::Future, sync::Arc, time::Duration};
+use std::{
+    borrow::Borrow,
+    error::Error as StdError,
+    future::Future,
+    net::SocketAddr,
+    sync::{Arc, atomic::Ordering},
+    time::Duration,
+};
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::{TcpListener, TcpStream, ToSocketAddrs},
     signal,
     time::timeout,
 };
-use tokio_rustls::rustls;
-use tokio_util::task::TaskTracker;
+use tokio_rustls::{rustls, server::TlsStream};
+use tokio_util::{either::Either, task::TaskTracker};
+use tracing::Instrument;
 
-use crate::proxy::cert::CertifiedKeyDer;
+use crate::{
+    policy::Policy,
+    proxy::{
+        auth::{self, ProxyUser},
+        cert::CertifiedKeyDer,
+        clients::{ClientConnection, PeerAddr, Throttled},
+        http3, metrics, socks,
+        status::TunnelGuard,
+        transparent, upstream,
+    },
+};
+
+/// `B` boxed with its own data and error types
+type BoxedAs<B> = BoxBody<<B as Body>::Data, <B as Body>::Error>;
 
 #[derive(Clone)]
 /// The main struct to run proxy server
@@ -48,9 +75,17 @@ where
 {
     /// Bind to a socket address and return a future that runs the proxy server.
     /// URL for requests that passed to service are full URL including scheme.
+    /// The listener speaks HTTP/1.1 and HTTP/2 with prior knowledge (h2c), `tls` wraps it in TLS (ALPN h2/http1.1).
+    /// `socks_addr` - optional SOCKS5/SOCKS4a listener served by the same service,
+    /// `transparent_addr` - optional listener for iptables `REDIRECT`ed connections,
+    /// `h3_addr` - optional UDP listener for `REDIRECT`ed QUIC connections.
     pub async fn bind<A: ToSocketAddrs, S>(
         self,
         addr: A,
+        tls: bool,
+        socks_addr: Option<A>,
+        transparent_addr: Option<A>,
+        h3_addr: Option<A>,
         service: S,
     ) -> Result<impl Future<Output = ()>, std::io::Error>
     where
@@ -62,8 +97,26 @@ where
         S::Future: Send,
     {
         let listener = TcpListener::bind(addr).await?;
+        let socks_listener = match socks_addr {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
+        let transparent_listener = match transparent_addr {
+            Some(addr) => Some(TcpListener::bind(addr).await?),
+            None => None,
+        };
 
         let proxy = Arc::new(self);
+        let h3_endpoint = match h3_addr {
+            Some(addr) => {
+                let addr = tokio::net::lookup_host(addr)
+                    .await?
+                    .next()
+                    .ok_or_else(|| std::io::Error::other("HTTP/3 listen address not resolved"))?;
+                Some(http3::endpoint(proxy.clone(), addr)?)
+            }
+            None => None,
+        };
 
         Ok(async move {
             let tracker = TaskTracker::new();
@@ -73,30 +126,84 @@ where
                         break;
                     },
                     c = listener.accept() =>{
-                        let (stream, _) = match c {
+                        let (stream, peer) = match c {
                             Ok(conn) => conn,
                             Err(err) => {
                                 tracing::warn!("Failed to accept connection: {}", err);
                                 continue;
                             }
                         };
+                        let Some(connection) = ClientConnection::open(peer) else {
+                            continue;
+                        };
                         let service = service.clone();
                         let proxy = proxy.clone();
                         let tracker_clone = tracker.clone();
                         tracker.spawn(async move {
-                            if let Err(err) = http_mitm_proxy::hyper::server::conn::http1::Builder::new()
-                                .preserve_header_case(true)
-                                .title_case_headers(true)
-                                .serve_connection(
-                                    TokioIo::new(stream),
-                                    MitmProxy::wrap_service(proxy.clone(), service.clone(), tracker_clone),
+                            let _connection = connection;
+                            let client = if tls {
+                                let Some(client) = proxy.accept_tls(stream, peer).await else {
+                                    return;
+                                };
+                                Either::Left(client)
+                            } else {
+                                Either::Right(Throttled::new(stream, peer))
+                            };
+                            let mut builder = auto::Builder::new(TokioExecutor::new());
+                            builder.http1().preserve_header_case(true).title_case_headers(true);
+                            if let Err(err) = builder
+                                .serve_connection_with_upgrades(
+                                    TokioIo::new(client),
+                                    MitmProxy::wrap_service(proxy.clone(), service.clone(), tracker_clone, peer),
                                 )
-                                .with_upgrades()
                                 .await
                             {
                                 tracing::error!("Error in proxy: {}", err);
                             }
-                        });
+                        }.instrument(tracing::info_span!("client", %peer)));
+                    }
+                    c = accept_optional(socks_listener.as_ref()) =>{
+                        let (stream, peer) = match c {
+                            Ok(conn) => conn,
+                            Err(err) => {
+                                tracing::warn!("Failed to accept SOCKS connection: {}", err);
+                                continue;
+                            }
+                        };
+                        let Some(connection) = ClientConnection::open(peer) else {
+                            continue;
+                        };
+                        let service = service.clone();
+                        let proxy = proxy.clone();
+                        tracker.spawn(async move {
+                            let _connection = connection;
+                            MitmProxy::serve_socks(proxy, stream, peer, service).await;
+                        }.instrument(tracing::info_span!("client", %peer)));
+                    }
+                    c = accept_optional(transparent_listener.as_ref()) =>{
+                        let (stream, peer) = match c {
+                            Ok(conn) => conn,
+                            Err(err) => {
+                                tracing::warn!("Failed to accept transparent connection: {}", err);
+                                continue;
+                            }
+                        };
+                        let Some(connection) = ClientConnection::open(peer) else {
+                            continue;
+                        };
+                        let service = service.clone();
+                        let proxy = proxy.clone();
+                        tracker.spawn(async move {
+                            let _connection = connection;
+                            MitmProxy::serve_transparent(proxy, stream, peer, service).await;
+                        }.instrument(tracing::info_span!("client", %peer)));
+                    }
+                    Some(incoming) = accept_quic(h3_endpoint.as_ref()) =>{
+                        let peer = incoming.remote_address();
+                        tracker.spawn(
+                            http3::serve(incoming, service.clone())
+                                .instrument(tracing::info_span!("client", %peer)),
+                        );
                     }
                 }
             }
@@ -118,14 +225,17 @@ where
     /// See `examples/https.rs` for usage.
     /// If you want to serve simple HTTP proxy server, you can use `bind` method instead.
     /// `bind` will call this method internally.
+    /// `peer` is added to the request extensions as `PeerAddr`.
     pub fn wrap_service<S>(
         proxy: Arc<Self>,
         service: S,
         tracker: TaskTracker,
-    ) -> impl HttpService<
-        Incoming,
-        ResBody = BoxBody<<S::ResBody as Body>::Data, <S::ResBody as Body>::Error>,
-        Future: Send,
+        peer: SocketAddr,
+    ) -> impl Service<
+        Request<Incoming>,
+        Response = Response<BoxedAs<S::ResBody>>,
+        Error = S::Error,
+        Future: Send + 'static,
     >
     where
         S: HttpService<Incoming> + Clone + Send + 'static,
@@ -135,12 +245,26 @@ where
         <S::ResBody as Body>::Error: Into<Box<dyn StdError + Send + Sync>>,
         S::Future: Send,
     {
-        service_fn(move |req| {
+        service_fn(move |mut req: Request<Incoming>| {
             let tracker = tracker.clone();
             let proxy = proxy.clone();
             let mut service = service.clone();
 
             async move {
+                let user = match auth::authenticate(req.method(), req.uri(), req.headers()) {
+                    Ok(user) => user,
+                    Err(challenge) => {
+                        tracing::debug!("Proxy authentication required for {}", req.uri());
+                        let mut res = no_body(StatusCode::PROXY_AUTHENTICATION_REQUIRED);
+                        challenge.apply(res.headers_mut());
+                        return Ok(res.map(|b| b.boxed().map_err(|never| match never {}).boxed()));
+                    }
+                };
+                req.extensions_mut().insert(PeerAddr(peer));
+                if let Some(user) = &user {
+                    req.extensions_mut().insert(user.clone());
+                }
+
                 if req.method() == Method::CONNECT {
                     // https
                     let Some(connect_authority) = req.uri().authority().cloned() else {
@@ -152,90 +276,32 @@ where
                             .map(|b| b.boxed().map_err(|never| match never {}).boxed()));
                     };
 
-                    tracker.spawn(async move {
-                        let client = match hyper::upgrade::on(req).await {
-                            Ok(client) => client,
-                            Err(err) => {
-                                tracing::error!(
-                                    "Failed to upgrade CONNECT request for {}: {}",
-                                    connect_authority,
-                                    err
-                                );
-                                return;
-                            }
-                        };
-                        if let Some(server_config) =
-                            proxy.server_config(connect_authority.host().to_string(), true)
-                        {
-                            let server_config = match server_config {
-                                Ok(server_config) => server_config,
-                                Err(err) => {
-                                    tracing::error!(
-                                        "Failed to create server config for {}, {}",
-                                        connect_authority.host(),
-                                        err
-                                    );
-                                    return;
-                                }
-                            };
-                            let server_config = Arc::new(server_config);
-                            let tls_acceptor = tokio_rustls::TlsAcceptor::from(server_config);
-                            let client = match tls_acceptor.accept(TokioIo::new(client)).await {
+                    tracker.spawn(
+                        async move {
+                            let _tunnel = TunnelGuard::open();
+                            let client = match hyper::upgrade::on(req).await {
                                 Ok(client) => client,
                                 Err(err) => {
                                     tracing::error!(
-                                        "Failed to accept TLS connection for {}, {}",
-                                        connect_authority.host(),
+                                        "Failed to upgrade CONNECT request for {}: {}",
+                                        connect_authority,
                                         err
                                     );
                                     return;
                                 }
                             };
-                            let f = move |mut req: Request<_>| {
-                                let connect_authority = connect_authority.clone();
-                                let mut service = service.clone();
-
-                                async move {
-                                    inject_authority(&mut req, connect_authority.clone());
-                                    service.call(req).await
-                                }
-                            };
-                            let res = if client.get_ref().1.alpn_protocol() == Some(b"h2") {
-                                server::conn::http2::Builder::new(TokioExecutor::new())
-                                    .serve_connection(TokioIo::new(client), service_fn(f))
-                                    .await
-                            } else {
-                                server::conn::http1::Builder::new()
-                                    .preserve_header_case(true)
-                                    .title_case_headers(true)
-                                    .serve_connection(TokioIo::new(client), service_fn(f))
-                                    .with_upgrades()
-                                    .await
-                            };
-
-                            if let Err(err) = res {
-                                tracing::debug!("Connection closed: {}", err);
-                            }
-                        } else {
-                            let mut server =
-                                match TcpStream::connect(connect_authority.as_str()).await {
-                                    Ok(server) => server,
-                                    Err(err) => {
-                                        tracing::error!(
-                                            "Failed to connect to {}: {}",
-                                            connect_authority,
-                                            err
-                                        );
-                                        return;
-                                    }
-                                };
-                            let _ = tokio::io::copy_bidirectional(
-                                &mut TokioIo::new(client),
-                                &mut server,
+                            MitmProxy::serve_tunnel(
+                                proxy,
+                                TokioIo::new(client),
+                                connect_authority,
+                                service,
+                                user,
+                                peer,
                             )
                             .await;
                         }
-                    });
+                        .instrument(tracing::Span::current()),
+                    );
 
                     Ok(Response::new(
                         http_body_util::Empty::new()
@@ -250,13 +316,235 @@ where
         })
     }
 
-    fn get_certified_key(
+    /// Serve the client side of a tunnel to `connect_authority`:
+    /// TLS interception, or passthrough for bypassed hosts and when the certificate can't be made.
+    async fn serve_tunnel<S, IO>(
+        proxy: Arc<Self>,
+        client: IO,
+        connect_authority: Authority,
+        service: S,
+        user: Option<ProxyUser>,
+        peer: SocketAddr,
+    ) where
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        S: HttpService<Incoming> + Clone + Send + 'static,
+        S::Error: Into<Box<dyn StdError + Send + Sync>>,
+        S::ResBody: Send + Sync + 'static,
+        <S::ResBody as Body>::Data: Send,
+        <S::ResBody as Body>::Error: Into<Box<dyn StdError + Send + Sync>>,
+        S::Future: Send,
+    {
+        let user_name = user.as_ref().map(ProxyUser::name);
+        let server_config = if Policy::is_bypassed_host(connect_authority.host(), user_name) {
+            None
+        } else {
+            proxy.server_config(connect_authority.host().to_string(), true)
+        };
+        let Some(server_config) = server_config else {
+            passthrough(client, &connect_authority, user_name).await;
+            return;
+        };
+        let server_config = match server_config {
+            Ok(server_config) => server_config,
+            Err(err) => {
+                tracing::error!(
+                    "Failed to create server config for {}, {}",
+                    connect_authority.host(),
+                    err
+                );
+                return;
+            }
+        };
+        let server_config = Arc::new(server_config);
+        let tls_acceptor = tokio_rustls::TlsAcceptor::from(server_config);
+        let client = match tls_acceptor.accept(client).await {
+            Ok(client) => client,
+            Err(err) => {
+                metrics::TLS_HANDSHAKE_FAILURES.fetch_add(1, Ordering::Relaxed);
+                tracing::error!(
+                    "Failed to accept TLS connection for {}, {}",
+                    connect_authority.host(),
+                    err
+                );
+                return;
+            }
+        };
+        let h2 = client.get_ref().1.alpn_protocol() == Some(b"h2");
+        let service = tunnel_service(service, connect_authority, Scheme::HTTPS, user, peer);
+        let res = if h2 {
+            server::conn::http2::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(client), service)
+                .await
+        } else {
+            server::conn::http1::Builder::new()
+                .preserve_header_case(true)
+                .title_case_headers(true)
+                .serve_connection(TokioIo::new(client), service)
+                .with_upgrades()
+                .await
+        };
+
+        if let Err(err) = res {
+            tracing::debug!("Connection closed: {}", err);
+        }
+    }
+
+    /// SOCKS5/SOCKS4a client connection
+    async fn serve_socks<S>(proxy: Arc<Self>, mut stream: TcpStream, peer: SocketAddr, service: S)
+    where
+        S: HttpService<Incoming> + Clone + Send + 'static,
+        S::Error: Into<Box<dyn StdError + Send + Sync>>,
+        S::ResBody: Send + Sync + 'static,
+        <S::ResBody as Body>::Data: Send,
+        <S::ResBody as Body>::Error: Into<Box<dyn StdError + Send + Sync>>,
+        S::Future: Send,
+    {
+        let socks::SocksRequest { authority, user } = match socks::handshake(&mut stream).await {
+            Ok(request) => request,
+            Err(err) => {
+                tracing::debug!("SOCKS handshake failed: {err}");
+                return;
+            }
+        };
+        MitmProxy::serve_stream(proxy, stream, peer, service, authority, user).await;
+    }
+
+    /// Connection redirected by iptables to the transparent listener
+    async fn serve_transparent<S>(proxy: Arc<Self>, stream: TcpStream, peer: SocketAddr, service: S)
+    where
+        S: HttpService<Incoming> + Clone + Send + 'static,
+        S::Error: Into<Box<dyn StdError + Send + Sync>>,
+        S::ResBody: Send + Sync + 'static,
+        <S::ResBody as Body>::Data: Send,
+        <S::ResBody as Body>::Error: Into<Box<dyn StdError + Send + Sync>>,
+        S::Future: Send,
+    {
+        let original = match transparent::original_dst(&stream) {
+            Ok(original) => original,
+            Err(err) => {
+                tracing::warn!("Can't get the original destination: {err}");
+                return;
+            }
+        };
+        // прямое подключение к порту без REDIRECT привело бы к петле
+        if stream.local_addr().is_ok_and(|local| local == original) {
+            tracing::warn!("Connection to the transparent listener was not redirected");
+            return;
+        }
+        let authority = match original.to_string().parse() {
+            Ok(authority) => authority,
+            Err(err) => {
+                tracing::warn!("Invalid original destination {original}: {err}");
+                return;
+            }
+        };
+        MitmProxy::serve_stream(proxy, stream, peer, service, authority, None).await;
+    }
+
+    /// SOCKS or transparent connection to `authority`: TLS is intercepted like CONNECT tunnels
+    /// (with the name from SNI), plain HTTP goes to the service, anything else (or a server which speaks first)
+    /// is tunneled as is.
+    async fn serve_stream<S>(
+        proxy: Arc<Self>,
+        stream: TcpStream,
+        peer: SocketAddr,
+        service: S,
+        mut authority: Authority,
+        user: Option<ProxyUser>,
+    ) where
+        S: HttpService<Incoming> + Clone + Send + 'static,
+        S::Error: Into<Box<dyn StdError + Send + Sync>>,
+        S::ResBody: Send + Sync + 'static,
+        <S::ResBody as Body>::Data: Send,
+        <S::ResBody as Body>::Error: Into<Box<dyn StdError + Send + Sync>>,
+        S::Future: Send,
+    {
+        let _tunnel = TunnelGuard::open();
+
+        let head = match transparent::peek_head(&stream).await {
+            Ok(head) => head,
+            Err(err) => {
+                tracing::debug!("Connection to {authority} closed: {err}");
+                return;
+            }
+        };
+        let client = Throttled::new(stream, peer);
+
+        if head.first() == Some(&0x16) {
+            // TLS handshake record
+            if let Some(name) = transparent::sni(&head)
+                && let Ok(named) = format!("{name}:{}", authority.port_u16().unwrap_or(443)).parse()
+            {
+                authority = named;
+            }
+            MitmProxy::serve_tunnel(proxy, client, authority, service, user, peer).await;
+        } else if is_http_request(&head) {
+            let service = tunnel_service(service, authority, Scheme::HTTP, user, peer);
+            if let Err(err) = server::conn::http1::Builder::new()
+                .preserve_header_case(true)
+                .title_case_headers(true)
+                .serve_connection(TokioIo::new(client), service)
+                .with_upgrades()
+                .await
+            {
+                tracing::debug!("Connection closed: {}", err);
+            }
+        } else {
+            passthrough(client, &authority, user.as_ref().map(ProxyUser::name)).await;
+        }
+    }
+
+    /// TLS handshake on the `https://` listener with a certificate for the name from SNI
+    /// (or the listener IP when the client sends none)
+    async fn accept_tls(
+        &self,
+        stream: TcpStream,
+        peer: SocketAddr,
+    ) -> Option<TlsStream<Throttled<TcpStream>>> {
+        let head = match transparent::peek_head(&stream).await {
+            Ok(head) => head,
+            Err(err) => {
+                tracing::debug!("Connection closed before TLS handshake: {err}");
+                return None;
+            }
+        };
+        let name = match transparent::sni(&head) {
+            Some(name) => name.to_string(),
+            None => stream.local_addr().ok()?.ip().to_canonical().to_string(),
+        };
+        let server_config = match self.server_config(name.clone(), true) {
+            Some(Ok(server_config)) => server_config,
+            Some(Err(err)) => {
+                tracing::error!("Failed to create server config for {name}, {err}");
+                return None;
+            }
+            None => {
+                tracing::error!("Failed to create certificate for {name}");
+                return None;
+            }
+        };
+        match tokio_rustls::TlsAcceptor::from(Arc::new(server_config))
+            .accept(Throttled::new(stream, peer))
+            .await
+        {
+            Ok(client) => Some(client),
+            Err(err) => {
+                metrics::TLS_HANDSHAKE_FAILURES.fetch_add(1, Ordering::Relaxed);
+                tracing::debug!("Failed to accept TLS connection to the proxy, {err}");
+                None
+            }
+        }
+    }
+
+    pub(super) fn get_certified_key(
         &self,
         host: String,
     ) -> Result<sled::IVec, Box<dyn std::error::Error + Send + Sync>> {
         if let Some(ivec) = self.cert_cache.get(&host)? {
+            metrics::CERT_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
             return Ok(ivec);
         }
+        metrics::CERT_CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
 
         let k_vec = CertifiedKeyDer::generate_cert(host.clone(), self.root_issuer.borrow())?;
         let ivec = sled::IVec::from(k_vec);
@@ -297,17 +585,98 @@ where
     }
 }
 
+async fn accept_optional(
+    listener: Option<&TcpListener>,
+) -> std::io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn accept_quic(endpoint: Option<&quinn::Endpoint>) -> Option<quinn::Incoming> {
+    match endpoint {
+        Some(endpoint) => endpoint.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
 fn no_body<D>(status: StatusCode) -> Response<Empty<D>> {
     let mut res = Response::new(Empty::new());
     *res.status_mut() = status;
     res
 }
 
-fn inject_authority<B>(request_middleman: &mut Request<B>, authority: hyper::http::uri::Authority) {
+/// Requests of a tunnel get its authority and the client identity
+pub(super) fn tunnel_service<S>(
+    service: S,
+    authority: Authority,
+    scheme: Scheme,
+    user: Option<ProxyUser>,
+    peer: SocketAddr,
+) -> impl HttpService<Incoming, ResBody = S::ResBody, Error = S::Error, Future: Send>
+where
+    S: HttpService<Incoming> + Clone + Send + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    S::ResBody: Send + Sync + 'static,
+    <S::ResBody as Body>::Data: Send,
+    <S::ResBody as Body>::Error: Into<Box<dyn StdError + Send + Sync>>,
+    S::Future: Send,
+{
+    service_fn(move |mut req: Request<Incoming>| {
+        let authority = authority.clone();
+        let scheme = scheme.clone();
+        let user = user.clone();
+        let mut service = service.clone();
+
+        async move {
+            inject_authority(&mut req, authority, scheme);
+            req.extensions_mut().insert(PeerAddr(peer));
+            if let Some(user) = user {
+                req.extensions_mut().insert(user);
+            }
+            service.call(req).await
+        }
+    })
+}
+
+async fn passthrough<IO>(mut client: IO, authority: &Authority, user: Option<&str>)
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut server = match upstream::connect(
+        authority.host(),
+        authority.port_u16().unwrap_or(443),
+        user,
+    )
+    .await
+    {
+        Ok(server) => server,
+        Err(err) => {
+            tracing::error!("Failed to connect to {}: {}", authority, err);
+            return;
+        }
+    };
+    let _ = tokio::io::copy_bidirectional(&mut client, &mut server).await;
+}
+
+// метод запроса HTTP/1.x: заглавные буквы и пробел
+fn is_http_request(head: &[u8]) -> bool {
+    let letters = head.iter().take_while(|b| b.is_ascii_uppercase()).count();
+    letters >= 3 && head.get(letters) == Some(&b' ')
+}
+
+fn inject_authority<B>(request_middleman: &mut Request<B>, authority: Authority, scheme: Scheme) {
     let mut parts = request_middleman.uri().clone().into_parts();
-    parts.scheme = Some(hyper::http::uri::Scheme::HTTPS);
+    // для plain HTTP из SOCKS и прозрачного режима имя хоста есть только в Host
+    let host = (scheme == Scheme::HTTP)
+        .then(|| request_middleman.headers().get(HOST))
+        .flatten()
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<Authority>().ok());
+    parts.scheme = Some(scheme);
     if parts.authority.is_none() {
-        parts.authority = Some(authority.clone());
+        parts.authority = Some(host.unwrap_or_else(|| authority.clone()));
     }
 
     match hyper::http::uri::Uri::from_parts(parts) {
@@ -1,10 +1,10 @@
 use crate::{
     cancelation_token::CancellationGuard,
     cli::{APP_NAME, CLI},
-    dac::DAC,
     highway_semaphore::HighwaySemaphore,
     in_headers, initable_static,
     maybe::UnifiedError,
+    policy::Policy,
     processors::{
         compression::CompressionAlgo,
         html::{self},
@@ -13,40 +13,57 @@ use crate::{
     proxy::{
         bytes_ext::BytesExt,
         cert::{BASE_DIRS, CERT_PATHS, load_root_issuer},
+        client::{Client, Failure, UpstreamError, timeout_secs},
+        error_page::ErrorPage,
         headers_map_ext::HeaderMapExt,
+        metrics::Outcome,
         mitm::MitmProxy,
         parts_ext::PartsExt,
         request_ext::RequestExt,
         response_ext::{BoxedResponse, ResponseExt},
     },
+    reload,
 };
 use bytes::{Bytes, BytesMut};
 use encoding_rs_io::DecodeReaderBytesBuilder;
-use http_body_util::BodyStream;
-use http_mitm_proxy::{
-    DefaultClient,
-    futures::{StreamExt, future::ready, stream},
-};
+use http_body_util::{BodyStream, combinators::BoxBody};
+use http_mitm_proxy::futures::{StreamExt, future::ready, stream};
 use hyper::{
-    Method, Request, StatusCode,
+    Method, Request, Response, StatusCode,
     body::{Frame, Incoming},
     header::{
         CACHE_CONTROL, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, PRAGMA, TRANSFER_ENCODING,
     },
 };
 use hyper_util::service::TowerToHyperService;
-use std::{io::Read, time::Duration};
-use tokio::signal;
+use std::{
+    io::Read,
+    sync::atomic::Ordering,
+    time::Instant,
+};
+use tokio::{signal, time::timeout};
 use tower::ServiceBuilder;
-use tower_http::timeout::ResponseBodyTimeout;
+use tower_http::timeout::TimeoutBody;
 
+pub mod auth;
 pub mod bytes_ext;
 pub mod cert;
+pub mod client;
+pub mod clients;
+pub mod error_page;
 pub mod headers_map_ext;
+pub mod http3;
+pub mod metrics;
 pub mod mitm;
 pub mod parts_ext;
+pub mod pool;
 pub mod request_ext;
 pub mod response_ext;
+pub mod savings;
+pub mod socks;
+pub mod status;
+pub mod transparent;
+pub mod upstream;
 
 initable_static! {
    SEM: HighwaySemaphore = || { HighwaySemaphore::new(num_cpus::get()) };
@@ -58,24 +75,77 @@ macro_rules! up_some {
             return Ok(val);
         }
     };
+    ($res:expr, $outcome:expr) => {
+        if let Some(val) = $res {
+            metrics::count($outcome);
+            return Ok(val);
+        }
+    };
+}
+
+// ошибку upstream отдаём клиенту страницей вместо обрыва соединения
+macro_rules! up_error {
+    ($page:expr, $err:expr) => {{
+        metrics::count(Outcome::Error);
+        return Ok($page.response(&UnifiedError::from($err)));
+    }};
+}
+
+macro_rules! up_send {
+    ($client:expr, $req:expr, $policy:expr, $page:expr) => {
+        match $client.send_request($req, $policy).await {
+            Ok(res) => res,
+            Err(err) => up_error!($page, err),
+        }
+    };
+}
+
+// то же, но ответ без тела от upstream учитываем в статистике экономии
+macro_rules! up_skipped {
+    ($host:expr, $content_type:expr, $outcome:expr, $res:expr, $avoided:expr) => {
+        if let Some(val) = $res {
+            savings::record_skipped($host, $content_type, $avoided);
+            metrics::count($outcome);
+            return Ok(val);
+        }
+    };
 }
 
 async fn handler(
     mut req: Request<Incoming>,
-    client: DefaultClient,
+    client: Client,
 ) -> Result<BoxedResponse, UnifiedError> {
+    status::REQUESTS.fetch_add(1, Ordering::Relaxed);
+    if let Some(peer) = req.peer_addr() {
+        clients::count_request(peer);
+    }
     up_some!(req.process_mitm_it()?);
 
     let accept = req.normalize_and_get_accept();
+    let host = req.uri().host().unwrap_or_default().to_ascii_lowercase();
 
     let cli = &*CLI;
-    
-    if cli.fast_304 {
-        up_some!(req.skip_if_browser_has_cached(&accept));
+    let policy = Policy::for_request(req.uri().host(), req.uri().path(), req.proxy_user());
+    let error_page = ErrorPage::new(&req, &accept);
+
+    if policy.bypass {
+        req.normalize_headers();
+        let (res, _) = up_send!(client, req, &policy, error_page);
+        let (mut parts, body_incoming) = res.into_parts();
+        parts.extensions.insert(policy);
+        metrics::count(Outcome::Passthrough);
+        return Ok(Response::from_parts(parts, BoxBody::new(metrics::counted(body_incoming))));
+    }
+
+    let requested_type = savings::requested_type(req.headers(), &accept);
+    up_skipped!(&host, requested_type, Outcome::Blocked, req.skip_if_blocked(&accept), 0);
+
+    if policy.fast_304 {
+        up_skipped!(&host, requested_type, Outcome::Fast304, req.skip_if_browser_has_cached(&accept), 0);
     }
 
-    if cli.skip_aux_resources {
-        up_some!(req.skip_media_or_favicon(&accept));
+    if policy.skip_aux_resources {
+        up_skipped!(&host, requested_type, Outcome::Skipped, req.skip_media_or_favicon(&accept), 0);
     }
 
     req.normalize_headers();
@@ -84,27 +154,29 @@ async fn handler(
     let uri = req.uri().to_string();
     let req_method = req.method().clone();
 
-    let (res, upgrade) = client.send_request(req).await?;
+    let (res, upgraded) = up_send!(client, req, &policy, error_page);
     let (mut parts, body_incoming) = res.into_parts();
 
-    up_some!(parts.skip_on_proxy_error());
+    up_some!(parts.skip_on_proxy_error(), Outcome::Error);
+
+    parts.extensions.insert(policy.clone());
 
     if !parts.contains_key(CACHE_CONTROL) && in_headers!(parts.headers, PRAGMA, "no-cache") {
         parts.set(CACHE_CONTROL, "no-cache");
     }
 
     // can_be_patched проверяем до content_length, чтобы мемоизировать can_be_patched с данными из req_headers 
-    if upgrade.is_none() && matches!(req_method, Method::GET | Method::POST) && parts.can_be_patched(Some(&req_headers)) {
+    if !upgraded && matches!(req_method, Method::GET | Method::POST) && parts.can_be_patched(Some(&req_headers)) {
         // проверка chunked: curl -v -k --http1.1 --proxy http://127.0.0.1:5151 --trace-ascii - http://httpbin.org/stream/5
         let content_length: usize = parts.headers.get_as(CONTENT_LENGTH);
         if content_length <= cli.transform_limit
         {
-            if cli.skip_aux_resources {
-                up_some!(parts.skip_media_or_font_or_favicon());
+            if policy.skip_aux_resources {
+                up_skipped!(&host, savings::content_type(&parts.headers), Outcome::Skipped, parts.skip_media_or_font_or_favicon(), content_length);
             }
 
-            if (cli.html_clean && in_headers!(parts.headers, CONTENT_TYPE, "text/html"*))
-                || (cli.image_scale > 0.0 
+            if ((policy.html_clean && in_headers!(parts.headers, CONTENT_TYPE, "text/html"*))
+                || (policy.image_scale > 0.0 
                 && !accept.starts_with("text/") //browser open in new tab
                 && accept.contains("image/webp")
                 && in_headers!(
@@ -114,145 +186,175 @@ async fn handler(
                         | "image/png"
                         | "image/gif"
                         | "image/webp"
-                ))
+                )))
+                && let Some(compression_algo) = CompressionAlgo::from_resp_headers(&parts.headers)
             {
-                if let Some(compression_algo) = CompressionAlgo::from_resp_headers(&parts.headers) {
-                    let mut buffer = BytesMut::with_capacity(content_length.max(64 * 1024)); //для content_length == 0 предаллоцируем столько чтобы влезла любая средняя html страница
-                    let mut body_stream = BodyStream::new(body_incoming);
-
-                    while let Some(result) = body_stream.next().await {
-                        let frame = result?;
-
-                        if let Ok(data) = frame.into_data() {
-                            if buffer.len() + data.len() > cli.transform_limit {
-                                // ПРЕВЫШЕНИЕ: Склеиваем и выходим
-                                let prefix = buffer.freeze();
-                                let combined_stream = stream::once(ready(Ok(Frame::data(prefix))))
-                                    .chain(stream::once(ready(Ok(Frame::data(data)))))
-                                    .chain(body_stream);
-
-                                return Ok(parts.response_from_stream(combined_stream));
-                            }
-                            buffer.extend_from_slice(&data);
-                        } else {
-                            // трейлеры или прочие фреймы — для HTML считаем концом данных
-                            // на здоровом сервере мы не должны попадать в эту ветку, так как parts.can_be_patched должен пропускать только картинки и документы для вкладок где нет явных заголовков Trailer
-                            break;
+                let mut buffer = BytesMut::with_capacity(content_length.max(64 * 1024)); //для content_length == 0 предаллоцируем столько чтобы влезла любая средняя html страница
+                let mut body_stream = BodyStream::new(body_incoming);
+
+                loop {
+                    let idle = timeout_secs(policy.body_idle_timeout);
+                    let frame = match timeout(idle, body_stream.next()).await {
+                        Ok(Some(Ok(frame))) => frame,
+                        Ok(Some(Err(err))) => up_error!(error_page, err),
+                        Ok(None) => break,
+                        Err(_) => up_error!(
+                            error_page,
+                            UpstreamError::new(
+                                Failure::SlowBody,
+                                &host,
+                                format!("no data for {}s", policy.body_idle_timeout)
+                            )
+                        ),
+                    };
+
+                    if let Ok(data) = frame.into_data() {
+                        if buffer.len() + data.len() > cli.transform_limit {
+                            // ПРЕВЫШЕНИЕ: Склеиваем и выходим
+                            let prefix = buffer.freeze();
+                            let combined_stream = stream::once(ready(Ok(Frame::data(prefix))))
+                                .chain(stream::once(ready(Ok(Frame::data(data)))))
+                                .chain(body_stream)
+                                .inspect(|frame| {
+                                    if let Ok(frame) = frame {
+                                        metrics::count_frame(frame);
+                                    }
+                                });
+
+                            metrics::count(Outcome::Passthrough);
+                            return Ok(parts.response_from_stream(combined_stream));
                         }
+                        buffer.extend_from_slice(&data);
+                    } else {
+                        // трейлеры или прочие фреймы — для HTML считаем концом данных
+                        // на здоровом сервере мы не должны попадать в эту ветку, так как parts.can_be_patched должен пропускать только картинки и документы для вкладок где нет явных заголовков Trailer
+                        break;
                     }
-                    let bytes = buffer.freeze();
+                }
+                let bytes = buffer.freeze();
 
-                    if bytes.is_empty() {
-                        parts.remove(TRANSFER_ENCODING);
-                        parts.remove(CONTENT_ENCODING);
-                        return Ok(parts.response_from_bytes(bytes));
-                    }
+                if bytes.is_empty() {
+                    parts.remove(TRANSFER_ENCODING);
+                    parts.remove(CONTENT_ENCODING);
+                    metrics::count(Outcome::Passthrough);
+                    return Ok(parts.response_from_bytes(bytes));
+                }
 
-                    let text_encoding = if in_headers!(parts.headers, CONTENT_TYPE, "image/"*) {
-                        None
-                    } else {
-                        Some(parts.headers.extract_encoding())
+                let text_encoding = if in_headers!(parts.headers, CONTENT_TYPE, "image/"*) {
+                    None
+                } else {
+                    Some(parts.headers.extract_encoding())
+                };
+
+                let permit = SEM.acquire(text_encoding.is_some()).await?;
+                let (original_type, original_len) = (savings::content_type(&parts.headers), bytes.len());
+
+                let (ctoken, _guard) = CancellationGuard::new();
+                macro_rules! c_guard {
+                    () => {
+                        if ctoken.is_cancelled() {
+                            return Err("task canceled".into());
+                        }
                     };
+                }
 
-                    let permit = SEM.acquire(text_encoding.is_some()).await?;
+                let async_load_styles =
+                    text_encoding.is_some() && policy.html_rechunk_size > 0 && parts.headers.csp_allow_inline_js_in_attrs();
 
-                    let (ctoken, _guard) = CancellationGuard::new();
-                    macro_rules! c_guard {
-                        () => {
-                            if ctoken.is_cancelled() {
-                                return Err("task canceled".into());
-                            }
-                        };
-                    }
+                let transform_started = Instant::now();
+                let (result_compression_algo, processed_bytes, content_type_changed) =
+                tokio::task::spawn_blocking(
+                    move || -> Result<(CompressionAlgo, bytes::Bytes, bool), UnifiedError> {
+                        c_guard!();
+                        let _ = permit;
 
-                    let async_load_styles =
-                        text_encoding.is_some() && cli.html_rechunk_size > 0 && parts.headers.csp_allow_inline_js_in_attrs();
+                        if let Some(encoding) = text_encoding {
+                            let mut html_reader = DecodeReaderBytesBuilder::new()
+                                .encoding(Some(encoding))
+                                .build(compression_algo.create_decompressor(bytes.as_ref()));
 
-                    let (result_compression_algo, processed_bytes, content_type_changed) =
-                    tokio::task::spawn_blocking(
-                        move || -> Result<(CompressionAlgo, bytes::Bytes, bool), UnifiedError> {
                             c_guard!();
-                            let _ = permit;
 
-                            if let Some(encoding) = text_encoding {
-                                let mut html_reader = DecodeReaderBytesBuilder::new()
-                                    .encoding(Some(encoding))
-                                    .build(compression_algo.create_decompressor(bytes.as_ref()));
+                            let mut html = String::new();
+
+                            Ok(match html_reader.read_to_string(&mut html) {
+                                Ok(_) => {                                      
+                                    let patched_html = html::minify(html, async_load_styles, &uri, &policy);
+                                    c_guard!();
+                                    let (algo, compressed) = CompressionAlgo::from_req_headers(&req_headers)
+                                        .try_compress(patched_html);
+                                    (algo, compressed, true)
+                                }
+                                Err(e) => {
+                                    tracing::warn!("Could not read html '{uri}': {e}");
+                                    (compression_algo, bytes.clone(), false)
+                                }
+                            })
+                        } else {
+                            let mut decompressed = Vec::new();
 
-                                c_guard!();
+                            let dres = compression_algo
+                                .create_decompressor(bytes.as_ref())
+                                .read_to_end(&mut decompressed)
+                                .map_err(UnifiedError::from);
 
-                                let mut html = String::new();
+                            c_guard!();
 
-                                Ok(match html_reader.read_to_string(&mut html) {
-                                    Ok(_) => {                                      
-                                        let patched_html = html::minify(html, async_load_styles, &uri);
-                                        c_guard!();
-                                        let (_0, _1) = CompressionAlgo::from_req_headers(&req_headers)
-                                            .try_compress(patched_html);
-                                        (_0, _1, true)
+                            Ok(
+                                match dres.and_then(|_| webp::thumbnail(decompressed, policy.image_scale)) {
+                                    Ok(data) => {
+                                        (CompressionAlgo::Uncompressed, Bytes::from(data), true)
                                     }
                                     Err(e) => {
-                                        tracing::warn!("Could not read html '{uri}': {e}");
-                                        (compression_algo, bytes.clone(), false)
+                                        tracing::warn!("Could not optimize image '{uri}': {e}");
+                                        (compression_algo, bytes, false)
                                     }
-                                })
-                            } else {
-                                let mut decompressed = Vec::new();
-
-                                let dres = compression_algo
-                                    .create_decompressor(bytes.as_ref())
-                                    .read_to_end(&mut decompressed)
-                                    .map_err(UnifiedError::from);
-
-                                c_guard!();
-
-                                Ok(
-                                    match dres.and_then(|_| webp::thumbnail(decompressed)) {
-                                        Ok(data) => {
-                                            (CompressionAlgo::Uncompressed, Bytes::from(data), true)
-                                        }
-                                        Err(e) => {
-                                            tracing::warn!("Could not optimize image '{uri}': {e}");
-                                            (compression_algo, bytes, false)
-                                        }
-                                    },
-                                )
-                            }
-                        },
-                    )
-                    .await??;
+                                },
+                            )
+                        }
+                    },
+                )
+                .await??;
+
+                if text_encoding.is_some() {
+                    metrics::TRANSFORM_HTML.observe(transform_started.elapsed());
+                } else {
+                    metrics::TRANSFORM_IMAGE.observe(transform_started.elapsed());
+                }
 
-                    parts.remove(TRANSFER_ENCODING);
+                parts.remove(TRANSFER_ENCODING);
 
-                    if content_type_changed {
-                        parts.set(
-                            CONTENT_TYPE,
-                            if text_encoding.is_some() {
-                                "text/html; charset=utf-8"
-                            } else {
-                                "image/webp"
-                            },
-                        );
-                    }
-                    if result_compression_algo != CompressionAlgo::Uncompressed {
-                        parts.set(CONTENT_ENCODING, result_compression_algo.as_str());
-                    } else {
-                        parts.remove(CONTENT_ENCODING);
-                    }
+                if content_type_changed {
+                    parts.set(
+                        CONTENT_TYPE,
+                        if text_encoding.is_some() {
+                            "text/html; charset=utf-8"
+                        } else {
+                            "image/webp"
+                        },
+                    );
+                }
+                if result_compression_algo != CompressionAlgo::Uncompressed {
+                    parts.set(CONTENT_ENCODING, result_compression_algo.as_str());
+                } else {
+                    parts.remove(CONTENT_ENCODING);
+                }
 
-                    
+                savings::record_transformed(&host, original_type, original_len, processed_bytes.len());
+                metrics::count(Outcome::Transformed);
+                metrics::count_bytes(original_len, processed_bytes.len());
 
-                    return Ok(parts.response_from_bytes(processed_bytes));
-                }
+                return Ok(parts.response_from_bytes(processed_bytes));
             }
         }
     }
-    Ok(parts.response_from_incoming(body_incoming))
+    metrics::count(Outcome::Passthrough);
+    Ok(parts.response_from_incoming(metrics::counted(body_incoming)))
 }
 
 async fn gracefull_shutdown_handler(
     req: Request<Incoming>,
-    client: DefaultClient,
+    client: Client,
 ) -> Result<BoxedResponse, UnifiedError> {
     let ver = req.version();
 
@@ -263,45 +365,91 @@ async fn gracefull_shutdown_handler(
 }
 
 pub async fn run() -> Result<(), UnifiedError> {
-    if let Some(dac_path) = &CLI.dac {
-        DAC::init(dac_path)?;
+    reload::init()?;
+    tokio::spawn(reload::watch());
+    status::start();
+
+    if let Some(addr) = &CLI.metrics_listen {
+        let listener = tokio::net::TcpListener::bind(addr.as_str()).await?;
+        tracing::info!("Metrics are served on http://{addr}/metrics");
+        tokio::spawn(metrics::serve(listener));
     }
+
     let db = sled::Config::new()
         .cache_capacity(2 * 1024 * 1024)
         .path(BASE_DIRS.cache_dir().join(APP_NAME).join("certs_db"))
         .open()?;
 
     let proxy = MitmProxy::new(load_root_issuer()?, db);
-    let client = DefaultClient::new();
-    let service = TowerToHyperService::new(ResponseBodyTimeout::new(
+    let client = Client::new()?;
+    if let Some(upstream) = &CLI.upstream_proxy {
+        tracing::info!("Outgoing connections go through the upstream proxy {upstream}");
+    }
+    let service = TowerToHyperService::new(
         ServiceBuilder::new()
             .map_response(|mut res: BoxedResponse| {
                 res.normalize_headers();
-                res
+                // пауза между кусками тела, по правилам хоста если ответ от upstream
+                let idle = res
+                    .extensions()
+                    .get::<Policy>()
+                    .map_or(CLI.body_idle_timeout, |p| p.body_idle_timeout);
+                res.map(|body| TimeoutBody::new(timeout_secs(idle), body))
             })
             .service_fn(move |req: Request<Incoming>| {
                 gracefull_shutdown_handler(req, client.clone())
             }),
-        Duration::from_secs(30),
-    ));
-
-    let server_bind_future = proxy.bind(&CLI.listen, service).await?;
+    );
 
-    let (host, port) = &CLI.listen;
+    let server_bind_future = proxy
+        .bind(
+            &CLI.listen.addr,
+            CLI.listen.tls,
+            CLI.socks_listen.as_ref(),
+            CLI.transparent_listen.as_ref(),
+            CLI.h3_listen.as_ref(),
+            service,
+        )
+        .await?;
+
+    let (host, port) = &CLI.listen.addr;
+    let scheme = CLI.listen.scheme();
+    if !(auth::is_enabled()
+        || host == "localhost"
+        || host.parse::<std::net::IpAddr>().is_ok_and(|ip| ip.is_loopback()))
+    {
+        tracing::warn!("Proxy on {host}:{port} is open to anyone who can reach it, consider --proxy-users");
+    }
     tracing::info!(
         "
-    HTTP Proxy is listening on http://{host}:{port}
+    HTTP Proxy is listening on {scheme}://{host}:{port}
     Trust this certificate in your browser/system to use HTTPS interception:
         Windows/Android: {}
         Other systems: {}
-    Enable proxy http://{host}:{port} in your browser/system and go to http://mitm.it for detailed info.    
+    Enable proxy {scheme}://{host}:{port} in your browser/system and go to http://mitm.it for detailed info.    
 ",
         CERT_PATHS.cert_cer_path.display(),
         CERT_PATHS.cert_pem_path.display(),
     );
+    if let Some((host, port)) = &CLI.socks_listen {
+        tracing::info!("SOCKS proxy is listening on {host}:{port}");
+    }
+    if let Some((host, port)) = &CLI.transparent_listen {
+        tracing::info!("Transparent proxy is listening on {host}:{port}");
+        if !clients::CLIENT_LIMITS::get().has_allow_list() {
+            tracing::warn!("Transparent proxy on {host}:{port} has no authentication, limit it with --allow-clients");
+        }
+    }
+    if let Some((host, port)) = &CLI.h3_listen {
+        tracing::info!("HTTP/3 is listening on udp {host}:{port}");
+        if !clients::CLIENT_LIMITS::get().has_allow_list() {
+            tracing::warn!("HTTP/3 on udp {host}:{port} has no authentication, limit it with --allow-clients");
+        }
+    }
 
     server_bind_future.await;
 
+    savings::log_summary();
     tracing::info!("Server has been shut down.");
     Ok(())
 }
@@ -1,8 +1,8 @@
 use std::pin::Pin;
 
 use crate::{
-    cli::CLI, in_headers, proxy::bytes_ext::BytesExt, proxy::headers_map_ext::HeaderMapExt,
-    proxy::response_ext::BoxedResponse,
+    cli::CLI, in_headers, policy::Policy, proxy::bytes_ext::BytesExt,
+    proxy::headers_map_ext::HeaderMapExt, proxy::response_ext::BoxedResponse,
 };
 use bytes::Bytes;
 use easy_ext::ext;
@@ -11,7 +11,7 @@ use http_body_util::{BodyStream, StreamBody};
 use http_mitm_proxy::futures::future::ready;
 use http_mitm_proxy::futures::{Stream, StreamExt, stream};
 use hyper::Response;
-use hyper::body::{Frame, Incoming};
+use hyper::body::{Body, Frame};
 use hyper::header::{ACCEPT_RANGES, CACHE_CONTROL, CONTENT_LENGTH};
 use hyper::http::response::Parts;
 use hyper::{
@@ -109,12 +109,18 @@ pub impl Parts {
         v
     }
 
+    fn rechunk_size(&self) -> usize {
+        self.extensions
+            .get::<Policy>()
+            .map_or(CLI.html_rechunk_size, |p| p.html_rechunk_size)
+    }
+
     fn must_be_rechunkified(&mut self) -> bool {
         if let Some(m) = self.extensions.get::<MustReChunkified>() {
             return m.0;
         }
 
-        let v = CLI.html_rechunk_size > 0
+        let v = self.rechunk_size() > 0
             && self.can_be_patched(None)
             && in_headers!(self.headers, CONTENT_TYPE, "text/html"*)
             && !in_headers!(self.headers, ACCEPT_RANGES, "bytes");
@@ -127,7 +133,7 @@ pub impl Parts {
         self.headers.inject_etag_marker();
         self.headers.normalize_extra_for_patched_content();
 
-        let chunk_size = CLI.html_rechunk_size;
+        let chunk_size = self.rechunk_size();
 
         let len = body.len();
         if len > chunk_size && self.must_be_rechunkified() {
@@ -148,7 +154,10 @@ pub impl Parts {
         }
     }
 
-    fn response_from_incoming(mut self, body: Incoming) -> BoxedResponse {
+    fn response_from_incoming<B>(mut self, body: B) -> BoxedResponse
+    where
+        B: Body<Data = Bytes, Error = hyper::Error> + Send + Sync + 'static,
+    {
         if self.must_be_rechunkified() {
             //for none RANGE responses rechunkify and send as chunked response
             self.response_from_stream(BodyStream::new(body))
@@ -162,7 +171,7 @@ pub impl Parts {
         S: Stream<Item = Result<Frame<Bytes>, hyper::Error>> + Send + Sync + 'static,
     {
         if self.must_be_rechunkified() {
-            let chunk_size = CLI.html_rechunk_size;
+            let chunk_size = self.rechunk_size();
             //for none RANGE responses rechunkify and send as chunked response
             self.remove(CONTENT_LENGTH);
             Response::from_parts(
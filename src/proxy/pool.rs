@@ -0,0 +1,339 @@
+use crate::maybe::UnifiedError;
+use bytes::Bytes;
+use http_body_util::combinators::BoxBody;
+use hyper::{
+    Request, Response, Version,
+    body::Incoming,
+    client::conn::{http1, http2},
+    header::HOST,
+};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::time::{MissedTickBehavior, interval};
+
+pub type RequestBody = BoxBody<Bytes, hyper::Error>;
+
+static OPENED: AtomicU64 = AtomicU64::new(0);
+static REUSED: AtomicU64 = AtomicU64::new(0);
+static IDLE: AtomicUsize = AtomicUsize::new(0);
+static HOSTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Connections of the same key are interchangeable
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct PoolKey {
+    pub https: bool,
+    pub host: String,
+    pub port: u16,
+    pub direct: bool,
+    pub insecure: bool,
+    pub pins: Vec<String>,
+}
+
+/// Handshaken upstream connection
+pub enum Sender {
+    Http1(http1::SendRequest<RequestBody>),
+    Http2(http2::SendRequest<RequestBody>),
+}
+
+impl Sender {
+    pub fn is_h2(&self) -> bool {
+        matches!(self, Sender::Http2(_))
+    }
+
+    fn is_closed(&self) -> bool {
+        match self {
+            Sender::Http1(sender) => sender.is_closed(),
+            Sender::Http2(sender) => sender.is_closed(),
+        }
+    }
+
+    /// HTTP/1.1 requests must already be in origin form (or absolute for a proxy), HTTP/2 ones absolute
+    pub async fn send(
+        &mut self,
+        mut req: Request<RequestBody>,
+    ) -> Result<Response<Incoming>, SendError> {
+        let res = match self {
+            Sender::Http1(sender) => {
+                *req.version_mut() = Version::HTTP_11;
+                if let Err(error) = sender.ready().await {
+                    return Err(SendError::unsent(error, req));
+                }
+                sender.try_send_request(req).await
+            }
+            Sender::Http2(sender) => {
+                *req.version_mut() = Version::HTTP_2;
+                req.headers_mut().remove(HOST);
+                if let Err(error) = sender.ready().await {
+                    return Err(SendError::unsent(error, req));
+                }
+                sender.try_send_request(req).await
+            }
+        };
+        res.map_err(|mut err| SendError {
+            unsent: err.take_message(),
+            error: err.into_error().into(),
+        })
+    }
+}
+
+/// Failed request; `unsent` is returned when nothing was written to the connection
+pub struct SendError {
+    pub error: UnifiedError,
+    pub unsent: Option<Request<RequestBody>>,
+}
+
+impl SendError {
+    fn unsent(error: hyper::Error, req: Request<RequestBody>) -> Self {
+        Self {
+            error: error.into(),
+            unsent: Some(req),
+        }
+    }
+}
+
+struct Idle {
+    sender: Sender,
+    since: Instant,
+}
+
+/// Keep-alive upstream connections. HTTP/1.1 ones return to the pool when the response body is read,
+/// an HTTP/2 one is shared by all requests to the host.
+pub struct Pool {
+    idle: Mutex<HashMap<PoolKey, Vec<Idle>>>,
+    idle_timeout: Duration,
+    max_idle_per_host: usize,
+}
+
+impl Pool {
+    /// Must be called inside the runtime: idle connections are closed by a background sweep
+    pub fn new(idle_timeout: Duration, max_idle_per_host: usize) -> Arc<Self> {
+        let pool = Arc::new(Self {
+            idle: Mutex::new(HashMap::new()),
+            idle_timeout,
+            max_idle_per_host,
+        });
+        if pool.enabled() {
+            let weak = Arc::downgrade(&pool);
+            let period = (idle_timeout / 2).max(Duration::from_secs(1));
+            tokio::spawn(async move {
+                let mut ticker = interval(period);
+                ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+                loop {
+                    ticker.tick().await;
+                    let Some(pool) = weak.upgrade() else {
+                        break;
+                    };
+                    let mut idle = pool.idle.lock();
+                    pool.expire(&mut idle);
+                    update_counts(&idle);
+                }
+            });
+        }
+        pool
+    }
+
+    fn enabled(&self) -> bool {
+        self.max_idle_per_host > 0 && !self.idle_timeout.is_zero()
+    }
+
+    /// Live connection for the key, if any
+    pub fn get(&self, key: &PoolKey) -> Option<Sender> {
+        if !self.enabled() {
+            return None;
+        }
+        let mut idle = self.idle.lock();
+        self.expire(&mut idle);
+        let list = idle.get_mut(key)?;
+        let mut found = None;
+        while let Some(mut entry) = list.pop() {
+            // закрытые сервером просто выбрасываются
+            match entry.sender {
+                Sender::Http1(sender) if sender.is_ready() => {
+                    found = Some(Sender::Http1(sender));
+                    break;
+                }
+                Sender::Http2(ref sender) if !sender.is_closed() => {
+                    found = Some(Sender::Http2(sender.clone()));
+                    entry.since = Instant::now();
+                    list.push(entry);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        if list.is_empty() {
+            idle.remove(key);
+        }
+        update_counts(&idle);
+        if found.is_some() {
+            REUSED.fetch_add(1, Ordering::Relaxed);
+        }
+        found
+    }
+
+    /// Count a new connection; HTTP/2 ones are shared at once
+    pub fn opened(&self, key: &PoolKey, sender: &Sender) {
+        OPENED.fetch_add(1, Ordering::Relaxed);
+        if let Sender::Http2(sender) = sender {
+            self.insert(key.clone(), Sender::Http2(sender.clone()));
+        }
+    }
+
+    /// Return an HTTP/1.1 connection when its current response is complete
+    pub fn put(self: &Arc<Self>, key: PoolKey, mut sender: http1::SendRequest<RequestBody>) {
+        if !self.enabled() {
+            return;
+        }
+        let pool = self.clone();
+        tokio::spawn(async move {
+            if sender.ready().await.is_ok() {
+                pool.insert(key, Sender::Http1(sender));
+            }
+        });
+    }
+
+    fn insert(&self, key: PoolKey, sender: Sender) {
+        if !self.enabled() {
+            return;
+        }
+        let mut idle = self.idle.lock();
+        self.expire(&mut idle);
+        let list = idle.entry(key).or_default();
+        if sender.is_h2() {
+            list.retain(|entry| !entry.sender.is_h2());
+        }
+        if list.len() < self.max_idle_per_host {
+            list.push(Idle {
+                sender,
+                since: Instant::now(),
+            });
+        }
+        update_counts(&idle);
+    }
+
+    // просроченные и закрытые сервером соединения закрываются вместе с SendRequest
+    fn expire(&self, idle: &mut HashMap<PoolKey, Vec<Idle>>) {
+        idle.retain(|_, list| {
+            list.retain(|entry| {
+                entry.since.elapsed() < self.idle_timeout && !entry.sender.is_closed()
+            });
+            !list.is_empty()
+        });
+    }
+}
+
+fn update_counts(idle: &HashMap<PoolKey, Vec<Idle>>) {
+    IDLE.store(idle.values().map(Vec::len).sum(), Ordering::Relaxed);
+    HOSTS.store(idle.len(), Ordering::Relaxed);
+}
+
+/// Pool counters for the status page
+pub struct PoolStats {
+    pub idle: usize,
+    pub hosts: usize,
+    pub opened: u64,
+    pub reused: u64,
+}
+
+pub fn stats() -> PoolStats {
+    PoolStats {
+        idle: IDLE.load(Ordering::Relaxed),
+        hosts: HOSTS.load(Ordering::Relaxed),
+        opened: OPENED.load(Ordering::Relaxed),
+        reused: REUSED.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Empty};
+    use hyper_util::rt::TokioIo;
+    use tokio::{
+        net::{TcpListener, TcpStream},
+        time::sleep,
+    };
+
+    fn run(test: impl Future<Output = ()>) {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(test);
+    }
+
+    fn key() -> PoolKey {
+        PoolKey {
+            https: false,
+            host: "example.com".to_string(),
+            port: 80,
+            direct: false,
+            insecure: false,
+            pins: Vec::new(),
+        }
+    }
+
+    // HTTP/1.1 соединение и серверная сторона сокета, сервер ничего не отвечает
+    async fn connection() -> (http1::SendRequest<RequestBody>, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        let (sender, conn) = http1::handshake(TokioIo::new(client)).await.unwrap();
+        tokio::spawn(conn);
+        (sender, server)
+    }
+
+    fn request() -> Request<RequestBody> {
+        Request::get("/")
+            .body(Empty::new().map_err(|never| match never {}).boxed())
+            .unwrap()
+    }
+
+    #[test]
+    fn idle_connections_expire() {
+        run(async {
+            let pool = Pool::new(Duration::from_millis(100), 2);
+            let mut servers = Vec::new();
+            for _ in 0..3 {
+                let (sender, server) = connection().await;
+                pool.insert(key(), Sender::Http1(sender));
+                servers.push(server);
+            }
+            // сверх max_idle_per_host не хранится
+            assert_eq!(pool.idle.lock()[&key()].len(), 2);
+
+            assert!(pool.get(&key()).is_some());
+            sleep(Duration::from_millis(150)).await;
+            assert!(pool.get(&key()).is_none());
+            assert!(pool.idle.lock().is_empty());
+        });
+    }
+
+    #[test]
+    fn closed_connection_is_dropped_and_its_request_returned() {
+        run(async {
+            let pool = Pool::new(Duration::from_secs(60), 2);
+            let (sender, server) = connection().await;
+            pool.insert(key(), Sender::Http1(sender));
+            drop(server);
+            sleep(Duration::from_millis(50)).await;
+            assert!(pool.get(&key()).is_none());
+
+            // запрос к закрытому соединению не записан, его можно отправить по новому
+            let (sender, server) = connection().await;
+            drop(server);
+            sleep(Duration::from_millis(50)).await;
+            let err = Sender::Http1(sender).send(request()).await.unwrap_err();
+            assert!(err.unsent.is_some_and(|req| req.uri() == "/"));
+        });
+    }
+}
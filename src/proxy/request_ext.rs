@@ -1,17 +1,29 @@
 use crate::{
+    dac::{DAC, UrlBaseInfo, psl, resource_type::ResourceTypes},
     in_headers, initable_static,
     maybe::UnifiedError,
     proxy::{
-        bytes_ext::BytesExt, cert::CERT_PATHS, headers_map_ext::HeaderMapExt,
-        response_ext::BoxedResponse,
+        auth::ProxyUser, bytes_ext::BytesExt, cert::CERT_PATHS, clients::PeerAddr,
+        headers_map_ext::HeaderMapExt, metrics, response_ext::BoxedResponse, savings, status,
     },
 };
 use bytes::Bytes;
 use easy_ext::ext;
 use hyper::{
     Method, Request, StatusCode,
-    header::{ACCEPT, CONNECTION, IF_MODIFIED_SINCE, IF_NONE_MATCH, PROXY_AUTHORIZATION, UPGRADE},
+    header::{
+        ACCEPT, CONNECTION, IF_MODIFIED_SINCE, IF_NONE_MATCH, PROXY_AUTHORIZATION, REFERER, UPGRADE,
+    },
 };
+use std::net::SocketAddr;
+use url::Url;
+
+/// 1x1 transparent GIF, stub for the blocked images
+const TRANSPARENT_GIF: [u8; 43] = [
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
 
 initable_static! {
    INSTRUCTION: Bytes = || { Bytes::from(include_str!(concat!(env!("OUT_DIR"), "/install.html"))) };
@@ -19,6 +31,16 @@ initable_static! {
 
 #[ext(RequestExt)]
 pub impl<T> Request<T> {
+    /// Client address of the listener connection
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.extensions().get::<PeerAddr>().map(|p| p.0)
+    }
+
+    /// Name of the user authenticated by the listener
+    fn proxy_user(&self) -> Option<&str> {
+        self.extensions().get::<ProxyUser>().map(ProxyUser::name)
+    }
+
     fn normalize_headers(&mut self) {
         let headers = self.headers_mut();
         if !in_headers!(headers, CONNECTION, *"upgrade"*) {
@@ -52,14 +74,13 @@ pub impl<T> Request<T> {
                     continue;
                 }
 
-                if let Some(p) = part.as_bytes().get(0..10) {
-                    if p.eq_ignore_ascii_case(b"image/avif")
+                if let Some(p) = part.as_bytes().get(0..10)
+                    && (p.eq_ignore_ascii_case(b"image/avif")
                         || p.eq_ignore_ascii_case(b"image/heic")
                         || p.eq_ignore_ascii_case(b"image/heif")
-                        || p.eq_ignore_ascii_case(b"image/apng")
-                    {
-                        continue;
-                    }
+                        || p.eq_ignore_ascii_case(b"image/apng"))
+                {
+                    continue;
                 }
 
                 if !accept.is_empty() {
@@ -76,14 +97,14 @@ pub impl<T> Request<T> {
         let accept_lower = accept.to_ascii_lowercase();
         headers.set_unchecked(ACCEPT, accept);
 
-        return accept_lower;
+        accept_lower
     }
 
     fn skip_if_browser_has_cached(&self, accept: &str) -> Option<BoxedResponse> {
         let h = self.headers();
 
         if *self.method() == Method::GET
-            && (in_headers!(h, IF_NONE_MATCH, *"zhlob~"*)
+            && (h.has_current_etag_marker()
                 || (h.contains_key(IF_MODIFIED_SINCE) || h.contains_key(IF_NONE_MATCH))
                     && matches!(
                         &accept.as_bytes()[..accept.len().min(6)],
@@ -124,6 +145,42 @@ pub impl<T> Request<T> {
         }
     }
 
+    /// Empty stub of the requested type if the request URL is blocked by DAC rules
+    fn skip_if_blocked(&self, accept: &str) -> Option<BoxedResponse> {
+        let dac = DAC.load_full()?;
+        let resource_type = ResourceTypes::from_request(self.headers(), accept)?;
+        let url = Url::parse(&self.uri().to_string()).ok()?;
+        // без Referer страницей считаем сам запрос, т.е. он first-party
+        let page = self
+            .headers()
+            .get(REFERER)
+            .and_then(|r| Url::parse(r.to_str().ok()?).ok())
+            .unwrap_or_else(|| url.clone());
+        let host = page.host_str()?.to_ascii_lowercase();
+        let etld_plus1 = psl::sld(&host)?.to_string();
+        let page = UrlBaseInfo {
+            host,
+            etld_plus1,
+            base: page,
+        };
+
+        if !dac.is_match_url(&url, &page, resource_type, 0) {
+            return None;
+        }
+        let (status, mime, body) = match resource_type {
+            ResourceTypes::SCRIPT => (StatusCode::OK, "application/javascript", Bytes::new()),
+            ResourceTypes::STYLESHEET => (StatusCode::OK, "text/css", Bytes::new()),
+            ResourceTypes::SUBDOCUMENT => (StatusCode::OK, "text/html", Bytes::new()),
+            ResourceTypes::IMAGE => (
+                StatusCode::OK,
+                "image/gif",
+                Bytes::from_static(&TRANSPARENT_GIF),
+            ),
+            _ => (StatusCode::NO_CONTENT, "", Bytes::new()),
+        };
+        Some(body.to_response(self.version(), status, mime))
+    }
+
     fn process_mitm_it(&self) -> Result<Option<BoxedResponse>, UnifiedError> {
         Ok(if self.uri().host() == Some("mitm.it") {
             let req_path = self.uri().path();
@@ -138,6 +195,24 @@ pub impl<T> Request<T> {
                     StatusCode::OK,
                     mime,
                 ))
+            } else if req_path == "/status" {
+                Some(Bytes::from(status::page()).to_response(
+                    self.version(),
+                    StatusCode::OK,
+                    "text/html; charset=utf-8",
+                ))
+            } else if req_path == "/metrics" {
+                Some(Bytes::from(metrics::render()).to_response(
+                    self.version(),
+                    StatusCode::OK,
+                    metrics::CONTENT_TYPE_TEXT,
+                ))
+            } else if req_path == "/savings" {
+                Some(Bytes::from(savings::report()).to_response(
+                    self.version(),
+                    StatusCode::OK,
+                    "text/plain; charset=utf-8",
+                ))
             } else {
                 Some(INSTRUCTION.clone().to_response(
                     self.version(),
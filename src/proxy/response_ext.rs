@@ -1,11 +1,16 @@
-use crate::{in_headers, proxy::headers_map_ext::HeaderMapExt};
+use crate::{
+    cli::CLI,
+    in_headers,
+    policy::{AltSvc, Policy},
+    proxy::headers_map_ext::HeaderMapExt,
+};
 use bytes::Bytes;
 use easy_ext::ext;
 use http_body_util::combinators::BoxBody;
 use hyper::{
     Response, StatusCode, Version,
     header::{
-        CACHE_CONTROL, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, EXPIRES, PRAGMA,
+        ALT_SVC, CACHE_CONTROL, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, EXPIRES, PRAGMA,
         PROXY_AUTHENTICATE, TRANSFER_ENCODING, UPGRADE,
     },
 };
@@ -17,6 +22,10 @@ pub impl<T> Response<T> {
     fn normalize_headers(&mut self) {
         let status = self.status();
         let ver = self.version();
+        let alt_svc = self
+            .extensions()
+            .get::<Policy>()
+            .map_or(AltSvc::Strip, |p| p.alt_svc);
         let hh = self.headers_mut();
 
         macro_rules! h_remove {
@@ -55,6 +64,21 @@ pub impl<T> Response<T> {
             hh.remove(UPGRADE);
         }
 
+        match alt_svc {
+            AltSvc::Keep => {}
+            AltSvc::Rewrite if CLI.h3_listen.is_some() && hh.contains_key(ALT_SVC) => {
+                match h3_alt_svc(&hh.get_safe(ALT_SVC)) {
+                    Some(v) => hh.set_unchecked(ALT_SVC, v),
+                    None => {
+                        hh.remove(ALT_SVC);
+                    }
+                }
+            }
+            _ => {
+                hh.remove(ALT_SVC);
+            }
+        }
+
         if in_headers!(hh, CACHE_CONTROL, *"max-age"* ) {
             hh.remove(EXPIRES);
         }
@@ -86,3 +110,59 @@ pub impl<T> Response<T> {
         }
     }
 }
+
+// Один h3 на том же хосте и порту 443: QUIC перехватывается iptables и попадает в --h3-listen,
+// а исходный порт там неизвестен, и запрос уходит на 443
+fn h3_alt_svc(value: &str) -> Option<String> {
+    let value = value.trim_ascii();
+    if value == "clear" {
+        return Some(value.to_string());
+    }
+    value.split(',').find_map(|alt| {
+        let (protocol, rest) = alt.trim_ascii().split_once('=')?;
+        if protocol != "h3" {
+            return None;
+        }
+        let (authority, params) = rest.split_once(';').unwrap_or((rest, ""));
+        authority.trim_matches('"').rsplit_once(':')?;
+        Some(if params.is_empty() {
+            "h3=\":443\"".to_string()
+        } else {
+            format!("h3=\":443\";{params}")
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alt_svc_clear_is_kept() {
+        assert_eq!(h3_alt_svc("clear").as_deref(), Some("clear"));
+        assert_eq!(h3_alt_svc(" clear ").as_deref(), Some("clear"));
+    }
+
+    #[test]
+    fn alt_svc_first_h3_moves_to_port_443() {
+        assert_eq!(
+            h3_alt_svc(r#"h3=":8443"; ma=86400"#).as_deref(),
+            Some(r#"h3=":443"; ma=86400"#)
+        );
+        assert_eq!(
+            h3_alt_svc(r#"h3-29=":443", h3=":8443"; ma=3600, h3=":9443""#).as_deref(),
+            Some(r#"h3=":443"; ma=3600"#)
+        );
+        assert_eq!(
+            h3_alt_svc(r#"h3="alt.example.com:443""#).as_deref(),
+            Some(r#"h3=":443""#)
+        );
+    }
+
+    #[test]
+    fn alt_svc_without_h3() {
+        assert_eq!(h3_alt_svc(r#"h2=":443"; ma=60"#), None);
+        assert_eq!(h3_alt_svc(r#"h3="example.com""#), None);
+        assert_eq!(h3_alt_svc(""), None);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::dac::resource_type::ResourceTypes;
+use hyper::{HeaderMap, header::CONTENT_TYPE};
+use parking_lot::Mutex;
+use std::{collections::BTreeMap, fmt::Write};
+
+// дальше новые хосты считаются вместе, чтобы статистика не росла без предела
+const MAX_HOSTS: usize = 1000;
+const OTHER_HOSTS: &str = "(other hosts)";
+
+/// Body sizes of the responses with the same host and content type
+#[derive(Default, Clone, Copy)]
+pub struct Traffic {
+    /// Responses transformed by the proxy
+    pub transformed: u64,
+    /// Responses answered without the upstream body: 204/304 stubs and blocked resources
+    pub skipped: u64,
+    /// Body size received from upstream or avoided by skipping (if known)
+    pub original: u64,
+    pub delivered: u64,
+}
+
+impl Traffic {
+    pub fn saved(&self) -> i64 {
+        self.original as i64 - self.delivered as i64
+    }
+
+    fn add(&mut self, other: &Traffic) {
+        self.transformed += other.transformed;
+        self.skipped += other.skipped;
+        self.original += other.original;
+        self.delivered += other.delivered;
+    }
+}
+
+// host -> content type -> traffic
+static SAVINGS: Mutex<BTreeMap<String, BTreeMap<&'static str, Traffic>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Kind of the response by its mime type: html, css, script, json, image, font, media, other or `-` if it's absent
+pub fn content_type(headers: &HeaderMap) -> &'static str {
+    let Some(mime) = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim_ascii().to_ascii_lowercase())
+        .filter(|v| !v.is_empty())
+    else {
+        return "-";
+    };
+    let (kind, subtype) = mime.split_once('/').unwrap_or((mime.as_str(), ""));
+    match (kind, subtype) {
+        ("text", "html") | ("application", "xhtml+xml") => "html",
+        ("text", "css") => "css",
+        (_, "javascript" | "ecmascript" | "x-javascript") => "script",
+        (_, "json") => "json",
+        (_, s) if s.ends_with("+json") => "json",
+        ("image", _) => "image",
+        ("font", _) | ("application", "font-woff" | "x-font-woff" | "vnd.ms-fontobject") => "font",
+        ("audio" | "video", _) => "media",
+        _ => "other",
+    }
+}
+
+/// Kind of the resource the browser asked for, in the terms of [`content_type`]
+pub fn requested_type(headers: &HeaderMap, accept: &str) -> &'static str {
+    match ResourceTypes::from_request(headers, accept) {
+        None => "html",
+        Some(t) if t.intersects(ResourceTypes::SUBDOCUMENT) => "html",
+        Some(t) if t.intersects(ResourceTypes::STYLESHEET) => "css",
+        Some(t) if t.intersects(ResourceTypes::SCRIPT) => "script",
+        Some(t) if t.intersects(ResourceTypes::IMAGE) => "image",
+        Some(t) if t.intersects(ResourceTypes::FONT) => "font",
+        Some(t) if t.intersects(ResourceTypes::MEDIA) => "media",
+        Some(_) => "other",
+    }
+}
+
+fn entry(host: &str, content_type: &'static str, f: impl FnOnce(&mut Traffic)) {
+    let mut savings = SAVINGS.lock();
+    let host = if savings.contains_key(host) || savings.len() < MAX_HOSTS {
+        host
+    } else {
+        OTHER_HOSTS
+    };
+    if !savings.contains_key(host) {
+        savings.insert(host.to_string(), BTreeMap::new());
+    }
+    if let Some(types) = savings.get_mut(host) {
+        f(types.entry(content_type).or_default());
+    }
+}
+
+/// Response body passed through `html::minify` or `webp::thumbnail` and compression
+pub fn record_transformed(
+    host: &str,
+    content_type: &'static str,
+    original: usize,
+    delivered: usize,
+) {
+    entry(host, content_type, |t| {
+        t.transformed += 1;
+        t.original += original as u64;
+        t.delivered += delivered as u64;
+    });
+}
+
+/// Response answered by the proxy itself instead of a resource of `content_type`,
+/// `avoided` - size of the upstream body (0 if it's unknown)
+pub fn record_skipped(host: &str, content_type: &'static str, avoided: usize) {
+    entry(host, content_type, |t| {
+        t.skipped += 1;
+        t.original += avoided as u64;
+    });
+}
+
+pub fn totals() -> Traffic {
+    let mut total = Traffic::default();
+    for t in SAVINGS.lock().values().flat_map(BTreeMap::values) {
+        total.add(t);
+    }
+    total
+}
+
+/// Per host totals sorted by saved bytes
+pub fn by_host() -> Vec<(String, Traffic)> {
+    let mut sorted: Vec<_> = SAVINGS
+        .lock()
+        .iter()
+        .map(|(host, types)| {
+            let mut total = Traffic::default();
+            for t in types.values() {
+                total.add(t);
+            }
+            (host.clone(), total)
+        })
+        .collect();
+    sorted.sort_by_key(|(_, t)| std::cmp::Reverse(t.saved()));
+    sorted
+}
+
+pub fn human_size(bytes: i64) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+    if bytes.abs() < 1024 {
+        return format!("{bytes} B");
+    }
+    let (mut size, mut unit) = (bytes as f64 / 1024.0, 0);
+    while size.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Plain text table for `http://mitm.it/savings`
+pub fn report() -> String {
+    let mut out = String::new();
+    let total = totals();
+    let _ = writeln!(
+        out,
+        "Saved {} of {} ({} transformed, {} skipped responses)\n",
+        human_size(total.saved()),
+        human_size(total.original as i64),
+        total.transformed,
+        total.skipped
+    );
+    let _ = writeln!(
+        out,
+        "{:<40} {:<28} {:>8} {:>8} {:>12} {:>12}",
+        "host", "content type", "transf.", "skipped", "original", "saved"
+    );
+    let savings = SAVINGS.lock();
+    let rows = savings
+        .iter()
+        .flat_map(|(host, types)| types.iter().map(move |(kind, t)| (host, kind, t)));
+    for (host, content_type, t) in rows {
+        let _ = writeln!(
+            out,
+            "{host:<40} {content_type:<28} {:>8} {:>8} {:>12} {:>12}",
+            t.transformed,
+            t.skipped,
+            human_size(t.original as i64),
+            human_size(t.saved())
+        );
+    }
+    out
+}
+
+/// Summary line on shutdown
+pub fn log_summary() {
+    let total = totals();
+    let top: Vec<String> = by_host()
+        .into_iter()
+        .take(5)
+        .map(|(host, t)| format!("{host}: {}", human_size(t.saved())))
+        .collect();
+    tracing::info!(
+        "Saved {} of {} ({} transformed, {} skipped responses). Top hosts: {}",
+        human_size(total.saved()),
+        human_size(total.original as i64),
+        total.transformed,
+        total.skipped,
+        if top.is_empty() {
+            "-".to_string()
+        } else {
+            top.join(", ")
+        }
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn kind(mime: &'static str) -> &'static str {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(mime));
+        content_type(&headers)
+    }
+
+    #[test]
+    fn content_types_are_grouped_by_kind() {
+        assert_eq!(kind("Text/HTML; charset=utf-8"), "html");
+        assert_eq!(kind("application/javascript"), "script");
+        assert_eq!(kind("application/ld+json"), "json");
+        assert_eq!(kind("image/webp"), "image");
+        assert_eq!(kind("application/font-woff"), "font");
+        assert_eq!(kind("video/mp4"), "media");
+        assert_eq!(kind("application/octet-stream"), "other");
+        assert_eq!(kind(" ; charset=utf-8"), "-");
+        assert_eq!(content_type(&HeaderMap::new()), "-");
+    }
+
+    #[test]
+    fn new_hosts_past_the_limit_share_one_entry() {
+        for i in 0..MAX_HOSTS + 10 {
+            record_skipped(&format!("host{i}.example"), "script", 100);
+        }
+        record_transformed("host0.example", "html", 1000, 400);
+
+        let hosts = by_host();
+        assert_eq!(hosts.len(), MAX_HOSTS + 1);
+        let other = hosts.iter().find(|(h, _)| h == OTHER_HOSTS).unwrap().1;
+        assert_eq!((other.skipped, other.original), (10, 1000));
+        assert_eq!(hosts[0].0, OTHER_HOSTS);
+        let host0 = hosts.iter().find(|(h, _)| h == "host0.example").unwrap().1;
+        assert_eq!((host0.transformed, host0.saved()), (1, 700));
+
+        let total = totals();
+        assert_eq!(
+            (total.transformed, total.skipped),
+            (1, MAX_HOSTS as u64 + 10)
+        );
+        assert!(report().contains("host0.example"));
+    }
+
+    #[test]
+    fn sizes_are_human_readable() {
+        assert_eq!(human_size(-512), "-512 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 << 30), "5.0 GiB");
+        assert_eq!(human_size(3 << 40), "3072.0 GiB");
+    }
+}
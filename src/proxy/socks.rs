@@ -0,0 +1,188 @@
+use crate::{
+    maybe::UnifiedError,
+    proxy::auth::{self, ProxyUser},
+};
+use hyper::http::uri::Authority;
+use std::{
+    io,
+    net::{Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::{sleep, timeout},
+};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Destination of the SOCKS CONNECT command
+pub struct SocksRequest {
+    pub authority: Authority,
+    pub user: Option<ProxyUser>,
+}
+
+/// Server side of SOCKS5 (RFC 1928, username/password from RFC 1929) and SOCKS4/4a handshakes.
+/// Only CONNECT is supported; success is replied before the upstream connection is made, as for HTTP CONNECT.
+/// Fails if the client doesn't finish it in 10 seconds.
+pub async fn handshake(stream: &mut TcpStream) -> Result<SocksRequest, UnifiedError> {
+    timeout(HANDSHAKE_TIMEOUT, exchange(stream))
+        .await
+        .map_err(|_| "SOCKS handshake timed out")?
+}
+
+async fn exchange(stream: &mut TcpStream) -> Result<SocksRequest, UnifiedError> {
+    // версия и число методов (SOCKS5) или команда (SOCKS4)
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head).await?;
+    match head {
+        [5, methods] => socks5(stream, methods).await,
+        [4, command] => socks4(stream, command).await,
+        [v, _] => Err(format!("Unsupported SOCKS version {v}").into()),
+    }
+}
+
+async fn socks5(stream: &mut TcpStream, methods: u8) -> Result<SocksRequest, UnifiedError> {
+    let mut methods = vec![0u8; methods as usize];
+    stream.read_exact(&mut methods).await?;
+
+    let user = if auth::is_enabled() {
+        if !methods.contains(&2) {
+            stream.write_all(&[5, 0xff]).await?;
+            return Err("SOCKS5 client doesn't support username/password authentication".into());
+        }
+        stream.write_all(&[5, 2]).await?;
+        let mut head = [0u8; 2];
+        stream.read_exact(&mut head).await?;
+        if head[0] != 1 {
+            return Err("Invalid SOCKS5 authentication version".into());
+        }
+        // имя и следом длина пароля
+        let mut name = vec![0u8; head[1] as usize + 1];
+        stream.read_exact(&mut name).await?;
+        let mut password = vec![0u8; name.pop().unwrap_or_default() as usize];
+        stream.read_exact(&mut password).await?;
+        let name = String::from_utf8(name)?;
+        let password = String::from_utf8(password)?;
+        match auth::check_password(&name, &password) {
+            Some(user) => {
+                stream.write_all(&[1, 0]).await?;
+                Some(user)
+            }
+            None => {
+                stream.write_all(&[1, 1]).await?;
+                return Err(format!("SOCKS5 authentication failed for '{name}'").into());
+            }
+        }
+    } else {
+        if !methods.contains(&0) {
+            stream.write_all(&[5, 0xff]).await?;
+            return Err("SOCKS5 client requires authentication".into());
+        }
+        stream.write_all(&[5, 0]).await?;
+        None
+    };
+
+    // VER CMD RSV ATYP и первый байт адреса (длина для имени хоста)
+    let mut head = [0u8; 5];
+    stream.read_exact(&mut head).await?;
+    if head[1] != 1 {
+        // command not supported
+        stream.write_all(&[5, 7, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+        return Err(format!("Unsupported SOCKS5 command {}", head[1]).into());
+    }
+    // остаток адреса и порт
+    let rest_len = match head[3] {
+        1 => 3 + 2,
+        3 => head[4] as usize + 2,
+        4 => 15 + 2,
+        _ => {
+            // address type not supported
+            stream.write_all(&[5, 8, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+            return Err("Unsupported SOCKS5 address type".into());
+        }
+    };
+    let mut rest = vec![0u8; rest_len];
+    stream.read_exact(&mut rest).await?;
+    let (addr, port) = rest.split_at(rest_len - 2);
+    let port = u16::from_be_bytes([port[0], port[1]]);
+    let host = match head[3] {
+        1 => Ipv4Addr::new(head[4], addr[0], addr[1], addr[2]).to_string(),
+        3 => String::from_utf8(addr.to_vec())?,
+        _ => {
+            let mut ip = [0u8; 16];
+            ip[0] = head[4];
+            ip[1..].copy_from_slice(addr);
+            format!("[{}]", Ipv6Addr::from(ip))
+        }
+    };
+    let authority = match format!("{host}:{port}").parse() {
+        Ok(authority) => authority,
+        Err(err) => {
+            stream.write_all(&[5, 1, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+            return Err(format!("Invalid SOCKS5 destination '{host}': {err}").into());
+        }
+    };
+    stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await?;
+    Ok(SocksRequest { authority, user })
+}
+
+async fn socks4(stream: &mut TcpStream, command: u8) -> Result<SocksRequest, UnifiedError> {
+    const REJECTED: [u8; 8] = [0, 0x5b, 0, 0, 0, 0, 0, 0];
+
+    let mut head = [0u8; 6];
+    stream.read_exact(&mut head).await?;
+    let port = u16::from_be_bytes([head[0], head[1]]);
+    let ip = [head[2], head[3], head[4], head[5]];
+    let _user_id = read_until_nul(stream).await?;
+
+    // 0.0.0.x - SOCKS4a, имя хоста после user id
+    let host = if ip[..3] == [0, 0, 0] && ip[3] != 0 {
+        read_until_nul(stream).await?
+    } else {
+        Ipv4Addr::from(ip).to_string()
+    };
+
+    if command != 1 {
+        stream.write_all(&REJECTED).await?;
+        return Err(format!("Unsupported SOCKS4 command {command}").into());
+    }
+    if auth::is_enabled() {
+        stream.write_all(&REJECTED).await?;
+        return Err("SOCKS4 has no passwords, use SOCKS5 when proxy users are set".into());
+    }
+    let authority = match format!("{host}:{port}").parse() {
+        Ok(authority) => authority,
+        Err(err) => {
+            stream.write_all(&REJECTED).await?;
+            return Err(format!("Invalid SOCKS4 destination '{host}': {err}").into());
+        }
+    };
+    stream.write_all(&[0, 0x5a, 0, 0, 0, 0, 0, 0]).await?;
+    Ok(SocksRequest {
+        authority,
+        user: None,
+    })
+}
+
+// у строк SOCKS4 нет длины: NUL ищется в ещё не прочитанных данных, чтобы не читать по байту
+async fn read_until_nul(stream: &mut TcpStream) -> Result<String, UnifiedError> {
+    let mut buf = [0u8; 256];
+    loop {
+        let n = stream.peek(&mut buf).await?;
+        if n == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        if let Some(end) = memchr::memchr(0, &buf[..n]) {
+            let mut s = vec![0u8; end + 1];
+            stream.read_exact(&mut s).await?;
+            s.pop();
+            return Ok(String::from_utf8(s)?);
+        }
+        if n == buf.len() {
+            return Err("Too long SOCKS4 string".into());
+        }
+        // остаток строки ещё в пути
+        sleep(Duration::from_millis(5)).await;
+    }
+}
@@ -0,0 +1,163 @@
+use crate::{
+    block_log,
+    cli::CLI,
+    dac::{DAC, psl},
+    proxy::{SEM, clients, pool, savings},
+};
+use std::{
+    fmt::Write,
+    sync::{
+        LazyLock,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
+pub static REQUESTS: AtomicU64 = AtomicU64::new(0);
+pub static ACTIVE_TUNNELS: AtomicUsize = AtomicUsize::new(0);
+static STARTED: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Counts the CONNECT tunnel as active while alive
+pub struct TunnelGuard;
+
+impl TunnelGuard {
+    pub fn open() -> Self {
+        ACTIVE_TUNNELS.fetch_add(1, Ordering::Relaxed);
+        TunnelGuard
+    }
+}
+
+impl Drop for TunnelGuard {
+    fn drop(&mut self) {
+        ACTIVE_TUNNELS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Start the uptime clock
+pub fn start() {
+    LazyLock::force(&STARTED);
+}
+
+pub fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// `http://mitm.it/status`: counters, savings, recent blocks and loaded lists
+pub fn page() -> String {
+    let mut rows = String::new();
+    let mut row = |name: &str, value: String| {
+        let _ = write!(rows, "<tr><th>{name}<td>{}", escape(&value));
+    };
+
+    let uptime = STARTED.elapsed().as_secs();
+    row(
+        "Uptime",
+        format!(
+            "{}d {:02}:{:02}:{:02}",
+            uptime / 86400,
+            uptime / 3600 % 24,
+            uptime / 60 % 60,
+            uptime % 60
+        ),
+    );
+    row("Requests", REQUESTS.load(Ordering::Relaxed).to_string());
+    row(
+        "Active tunnels",
+        ACTIVE_TUNNELS.load(Ordering::Relaxed).to_string(),
+    );
+    let (available, high, low) = SEM.queue_depth();
+    row(
+        "Transform queue",
+        format!("{available} free, {high} html + {low} images waiting"),
+    );
+    let total = savings::totals();
+    row(
+        "Saved",
+        format!(
+            "{} of {}",
+            savings::human_size(total.saved()),
+            savings::human_size(total.original as i64)
+        ),
+    );
+    match DAC.load_full() {
+        Some(dac) => row(
+            "DAC",
+            format!(
+                "{:016x}, {} patterns, {} domain, {} wildcard, {} regex",
+                dac.hash,
+                dac.pattern_count
+                    .map_or_else(|| "?".to_string(), |c| c.to_string()),
+                dac.domains.len(),
+                dac.wildcards.len(),
+                dac.regexes.len()
+            ),
+        ),
+        None => row("DAC", "not loaded".to_string()),
+    }
+    row("PSL", psl::source());
+    row(
+        "Upstream proxy",
+        CLI.upstream_proxy
+            .as_ref()
+            .map_or_else(|| "direct".to_string(), |u| u.to_string()),
+    );
+    let pool = pool::stats();
+    row(
+        "Upstream connections",
+        format!(
+            "{} pooled to {} hosts, {} opened, {} reused",
+            pool.idle, pool.hosts, pool.opened, pool.reused
+        ),
+    );
+
+    let mut hosts = String::new();
+    for (host, t) in savings::by_host().into_iter().take(10) {
+        let _ = write!(
+            hosts,
+            "<tr><td>{}<td>{}",
+            escape(&host),
+            savings::human_size(t.saved())
+        );
+    }
+
+    let mut clients = String::new();
+    for (ip, c) in clients::snapshot() {
+        let _ = write!(
+            clients,
+            "<tr><td>{ip}<td>{} conn.<td>{} req.<td>{}",
+            c.connections,
+            c.requests,
+            savings::human_size(c.bytes as i64)
+        );
+    }
+
+    let mut blocks = String::new();
+    for e in block_log::recent() {
+        let _ = write!(
+            blocks,
+            "<tr><td>{}<td>{}<td>{}",
+            escape(&e.resource_type),
+            escape(&e.url),
+            escape(&e.rule)
+        );
+    }
+
+    format!(
+        r#"<!DOCTYPE html><html><head><meta charset="utf-8"><meta http-equiv="refresh" content="10"><meta name="viewport" content="width=device-width"><title>Status</title><style>td,th{{text-align:left;padding:0 8px 0 0;word-break:break-all}}</style></head><body>
+<h3>Status</h3><table>{rows}</table>
+<h3>Clients</h3><table>{clients}</table>
+<h3>Saved by host</h3><table>{hosts}</table><a href="/savings">details</a>
+<h3>Recent blocks</h3><table>{blocks}</table>
+</body></html>"#
+    )
+}
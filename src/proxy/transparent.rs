@@ -0,0 +1,254 @@
+use std::{io, net::SocketAddr, time::Duration};
+use tokio::{
+    net::TcpStream,
+    time::{Instant, sleep, timeout_at},
+};
+
+/// Destination of the connection before the iptables `REDIRECT`/`DNAT`
+#[cfg(target_os = "linux")]
+pub fn original_dst(stream: &TcpStream) -> io::Result<SocketAddr> {
+    use std::{
+        mem,
+        net::{Ipv4Addr, Ipv6Addr},
+        os::fd::AsRawFd,
+    };
+    // SO_ORIGINAL_DST и IP6T_SO_ORIGINAL_DST из linux/netfilter_ipv{4,6}.h
+    const SO_ORIGINAL_DST: libc::c_int = 80;
+
+    let level = if stream.local_addr()?.is_ipv6() {
+        libc::SOL_IPV6
+    } else {
+        libc::SOL_IP
+    };
+    // SAFETY: getsockopt пишет не больше len байт в sockaddr_storage, который вмещает любой адрес
+    unsafe {
+        let mut addr: libc::sockaddr_storage = mem::zeroed();
+        let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+        if libc::getsockopt(
+            stream.as_raw_fd(),
+            level,
+            SO_ORIGINAL_DST,
+            &mut addr as *mut _ as *mut libc::c_void,
+            &mut len,
+        ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        match addr.ss_family as libc::c_int {
+            libc::AF_INET => {
+                let a = &*(&addr as *const _ as *const libc::sockaddr_in);
+                Ok(SocketAddr::from((
+                    Ipv4Addr::from(u32::from_be(a.sin_addr.s_addr)),
+                    u16::from_be(a.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                let a = &*(&addr as *const _ as *const libc::sockaddr_in6);
+                Ok(SocketAddr::from((
+                    Ipv6Addr::from(a.sin6_addr.s6_addr),
+                    u16::from_be(a.sin6_port),
+                )))
+            }
+            family => Err(io::Error::other(format!("Unknown address family {family}"))),
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn original_dst(_stream: &TcpStream) -> io::Result<SocketAddr> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Transparent mode is supported only on Linux",
+    ))
+}
+
+/// First bytes of the client stream without consuming them: the whole TLS ClientHello record (to read SNI)
+/// or whatever came first for other protocols. Empty if the client is silent for a second (server speaks first).
+pub async fn peek_head(stream: &TcpStream) -> io::Result<Vec<u8>> {
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut buf = vec![0u8; 5 + 16 * 1024];
+    let mut n = 0;
+    while let Ok(res) = timeout_at(deadline, stream.peek(&mut buf)).await {
+        n = res?;
+        let complete = match buf[..n] {
+            [] => true,
+            [0x16, _, _, h, l, ..] => n >= 5 + u16::from_be_bytes([h, l]) as usize,
+            [0x16, ..] => false,
+            _ => true,
+        };
+        if complete || Instant::now() >= deadline {
+            break;
+        }
+        // ClientHello с post-quantum ключами не влезает в один сегмент
+        sleep(Duration::from_millis(5)).await;
+    }
+    buf.truncate(n);
+    Ok(buf)
+}
+
+/// Server name from the TLS ClientHello record
+pub fn sni(record: &[u8]) -> Option<&str> {
+    let mut r = Reader(record);
+    if r.u8()? != 0x16 {
+        return None;
+    }
+    r.skip(4)?; // version, length
+    if r.u8()? != 1 {
+        return None; // not ClientHello
+    }
+    r.skip(3 + 2 + 32)?; // length, client_version, random
+    let n = r.u8()? as usize;
+    r.skip(n)?; // session_id
+    let n = r.u16()? as usize;
+    r.skip(n)?; // cipher_suites
+    let n = r.u8()? as usize;
+    r.skip(n)?; // compression_methods
+
+    let n = r.u16()? as usize;
+    let mut extensions = Reader(r.take(n)?);
+    while let (Some(kind), Some(len)) = (extensions.u16(), extensions.u16()) {
+        let data = extensions.take(len as usize)?;
+        if kind != 0 {
+            continue;
+        }
+        // server_name_list: name_type 0 - host_name
+        let mut names = Reader(data);
+        let n = names.u16()? as usize;
+        let mut list = Reader(names.take(n)?);
+        while let Some(name_type) = list.u8() {
+            let n = list.u16()? as usize;
+            let name = list.take(n)?;
+            if name_type == 0 {
+                return std::str::from_utf8(name).ok().filter(|s| !s.is_empty());
+            }
+        }
+        return None;
+    }
+    None
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let (head, tail) = self.0.split_at_checked(n)?;
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{io::AsyncWriteExt, net::TcpListener};
+
+    fn client_hello(extensions: &[u8]) -> Vec<u8> {
+        let mut body = vec![3, 3]; // client_version
+        body.extend([0u8; 32]); // random
+        body.push(0); // session_id
+        body.extend([0, 2, 0x13, 0x01]); // cipher_suites
+        body.extend([1, 0]); // compression_methods
+        body.extend((extensions.len() as u16).to_be_bytes());
+        body.extend(extensions);
+        let mut handshake = vec![1];
+        handshake.extend(&(body.len() as u32).to_be_bytes()[1..]);
+        handshake.extend(body);
+        let mut record = vec![0x16, 3, 1];
+        record.extend((handshake.len() as u16).to_be_bytes());
+        record.extend(handshake);
+        record
+    }
+
+    fn server_name(name: &str) -> Vec<u8> {
+        let mut list = vec![0];
+        list.extend((name.len() as u16).to_be_bytes());
+        list.extend(name.as_bytes());
+        let mut ext = vec![0, 0];
+        ext.extend((list.len() as u16 + 2).to_be_bytes());
+        ext.extend((list.len() as u16).to_be_bytes());
+        ext.extend(list);
+        ext
+    }
+
+    // ec_point_formats
+    const OTHER_EXTENSION: [u8; 6] = [0, 0x0b, 0, 2, 1, 0];
+
+    #[test]
+    fn sni_of_client_hello() {
+        let mut extensions = OTHER_EXTENSION.to_vec();
+        extensions.extend(server_name("example.com"));
+        assert_eq!(sni(&client_hello(&extensions)), Some("example.com"));
+    }
+
+    #[test]
+    fn sni_missing() {
+        assert_eq!(sni(&client_hello(&OTHER_EXTENSION)), None);
+        assert_eq!(sni(&client_hello(&server_name(""))), None);
+        assert_eq!(sni(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n"), None);
+    }
+
+    #[test]
+    fn sni_of_truncated_client_hello() {
+        let record = client_hello(&server_name("example.com"));
+        for len in 0..record.len() {
+            assert_eq!(sni(&record[..len]), None, "{len} bytes");
+        }
+    }
+
+    async fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    fn run(test: impl Future<Output = ()>) {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(test);
+    }
+
+    #[test]
+    fn peek_head_waits_for_the_whole_record() {
+        run(async {
+            let (mut client, server) = pair().await;
+            let record = client_hello(&server_name("example.com"));
+            client.write_all(&record[..10]).await.unwrap();
+            tokio::spawn(async move {
+                sleep(Duration::from_millis(50)).await;
+                client.write_all(&record[10..]).await.unwrap();
+                sleep(Duration::from_secs(2)).await;
+            });
+            let head = peek_head(&server).await.unwrap();
+            assert_eq!(sni(&head), Some("example.com"));
+        });
+    }
+
+    #[test]
+    fn peek_head_gives_up_on_truncated_record() {
+        run(async {
+            let (mut client, server) = pair().await;
+            let record = client_hello(&server_name("example.com"));
+            client.write_all(&record[..20]).await.unwrap();
+            let head = peek_head(&server).await.unwrap();
+            assert_eq!(head, record[..20]);
+            assert_eq!(sni(&head), None);
+        });
+    }
+}
@@ -0,0 +1,181 @@
+use crate::{
+    cli::{CLI, Cli},
+    config::{self, CONFIG, FileConfig},
+    dac::{DAC, Dac, psl::PS_LIST},
+    initable_static,
+    maybe::UnifiedError,
+    policy::{POLICY_RULES, PolicyRules},
+    proxy::{
+        auth::PROXY_USERS,
+        clients::{CLIENT_LIMITS, ClientLimits},
+    },
+};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+initable_static! {
+    ETAG_MARKER = reloadable |dac: Option<&Dac>, config: &FileConfig| -> Result<String, UnifiedError> {
+        // ETag трансформированного контента зависит от версии DAC и настроек, поэтому при их смене браузер получит новый контент
+        let h = dac.map_or(0, |d| d.hash) ^ config.hash;
+        Ok(if h == 0 {
+            "zhlob~~".to_string()
+        } else {
+            format!("zhlob~{}~", URL_SAFE_NO_PAD.encode(h.to_le_bytes()))
+        })
+    };
+}
+
+// files to watch with their last modification time
+static WATCHED: Mutex<Vec<(PathBuf, Option<SystemTime>)>> = Mutex::new(Vec::new());
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn watched_files(cli: &Cli, config: &FileConfig) -> Vec<(PathBuf, Option<SystemTime>)> {
+    [config.path.as_ref(), cli.dac.as_ref(), cli.psl.as_ref()]
+        .into_iter()
+        .flatten()
+        .filter(|p| *p != Path::new("-"))
+        .map(|p| (p.clone(), modified(p)))
+        .collect()
+}
+
+/// Values built from the config, stored together only when all of them are valid
+struct Loaded {
+    dac: Option<Arc<Dac>>,
+    policy: Arc<PolicyRules>,
+    users: Arc<HashMap<String, String>>,
+    limits: Arc<ClientLimits>,
+    etag_marker: Arc<String>,
+}
+
+impl Loaded {
+    fn build(cli: &Cli, config: &FileConfig) -> Result<Self, UnifiedError> {
+        let dac = cli.dac.as_ref().map(DAC::build).transpose()?;
+        let users = PROXY_USERS::build(cli, config)?;
+        let limits = CLIENT_LIMITS::build(cli)?;
+        // клиенты transparent и HTTP/3 слушателей не могут передать логин и обошли бы --proxy-users
+        if !users.is_empty() && !limits.has_allow_list() {
+            for (flag, listen) in [
+                ("--transparent-listen", &CLI.transparent_listen),
+                ("--h3-listen", &CLI.h3_listen),
+            ] {
+                if listen.is_some() {
+                    return Err(format!(
+                        "{flag} can't authenticate clients, with --proxy-users limit them with --allow-clients"
+                    )
+                    .into());
+                }
+            }
+        }
+        Ok(Self {
+            policy: POLICY_RULES::build(cli, config)?,
+            users,
+            limits,
+            etag_marker: ETAG_MARKER::build(dac.as_deref(), config)?,
+            dac,
+        })
+    }
+
+    fn store(self) {
+        DAC.store(self.dac);
+        POLICY_RULES::store(self.policy);
+        PROXY_USERS::store(self.users);
+        CLIENT_LIMITS::store(self.limits);
+        ETAG_MARKER::store(self.etag_marker);
+    }
+}
+
+/// Load DAC, policy rules and ETag marker at startup
+pub fn init() -> Result<(), UnifiedError> {
+    let config = CONFIG::get();
+    Loaded::build(&CLI, &config)?.store();
+    *WATCHED.lock() = watched_files(&CLI, &config);
+    Ok(())
+}
+
+fn reload() -> Result<(), UnifiedError> {
+    // lock also prevents concurrent reloads
+    let mut watched = WATCHED.lock();
+    // запоминаем до сборки: файл с ошибкой сообщит о ней один раз на изменение, а не на каждой проверке
+    for (path, mtime) in watched.iter_mut() {
+        *mtime = modified(path);
+    }
+
+    let config = CONFIG::build(config::config_path())?;
+    let (cli, matches) = config::parse_cli(&config)?;
+    let ps_list = if cli.psl.as_deref() != Some(Path::new("-")) {
+        Some(PS_LIST::build(&cli.psl)?)
+    } else {
+        None
+    };
+    let loaded = Loaded::build(&cli, &config)?;
+
+    // при ошибке выше остаются прежние версии всего
+    CONFIG::store(config.clone());
+    if let Some(ps_list) = ps_list {
+        PS_LIST::store(ps_list);
+    }
+    loaded.store();
+
+    let changed = config::changed_restart_flags(&matches);
+    if !changed.is_empty() {
+        tracing::warn!(
+            "Changed {} are not reloaded, restart to apply them",
+            changed.join(", ")
+        );
+    }
+
+    *watched = watched_files(&cli, &config);
+    Ok(())
+}
+
+async fn reload_logged(reason: &str) {
+    tracing::info!("Reloading config, DAC and PSL ({reason})...");
+    match tokio::task::spawn_blocking(reload).await {
+        Ok(Ok(_)) => tracing::info!("Reloaded. New ETag marker: {}", ETAG_MARKER::get()),
+        Ok(Err(e)) => tracing::error!("Reload failed, keep using the previous versions: {e}"),
+        Err(e) => tracing::error!("Reload failed: {e}"),
+    }
+}
+
+fn is_changed() -> bool {
+    WATCHED
+        .lock()
+        .iter()
+        .any(|(path, mtime)| modified(path) != *mtime)
+}
+
+/// Reload on SIGHUP and on changes of the watched files (if `--watch-interval` is set).
+/// In-flight requests keep using versions loaded at their start.
+pub async fn watch() {
+    #[cfg(unix)]
+    tokio::spawn(async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                while hangup.recv().await.is_some() {
+                    reload_logged("SIGHUP").await;
+                }
+            }
+            Err(e) => tracing::warn!("Could not listen for SIGHUP: {e}"),
+        }
+    });
+
+    if let Some(secs) = CLI.watch_interval {
+        let mut interval = tokio::time::interval(Duration::from_secs(secs.max(1) as u64));
+        loop {
+            interval.tick().await;
+            if is_changed() {
+                reload_logged("files changed").await;
+            }
+        }
+    }
+}